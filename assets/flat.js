@@ -0,0 +1,23 @@
+const body = document.querySelector('#files tbody');
+const rows = Array.from(body.rows);
+document.getElementById('filter').addEventListener('input', event => {
+    const needle = event.target.value.toLowerCase();
+    rows.forEach(row => {
+        row.hidden = !row.cells[0].textContent.toLowerCase().includes(needle);
+    });
+});
+document.querySelectorAll('#files th').forEach((header, column) => {
+    header.style.cursor = 'pointer';
+    header.addEventListener('click', () => {
+        const ascending = header.dataset.order !== 'asc';
+        header.dataset.order = ascending ? 'asc' : 'desc';
+        const key = row => column === 0
+            ? row.cells[0].textContent
+            : Number(row.cells[column].dataset.value);
+        rows.sort((a, b) => {
+            const x = key(a), y = key(b);
+            return (x < y ? -1 : x > y ? 1 : 0) * (ascending ? 1 : -1);
+        });
+        rows.forEach(row => body.appendChild(row));
+    });
+});
@@ -0,0 +1,571 @@
+/*
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ * More licensing information can be found in the project LICENSE file
+ * Author: Harshit Jain
+ * Email: reach@harsh1998.dev
+ */
+
+//! Per-directory `.hdl_access` files, which tighten or extend the server
+//! configuration for the directory they sit in and everything below it:
+//!
+//! ```text
+//! # Comments start with '#'
+//! listing = off          # no generated listing, /_flat or /_tree children
+//! extensions = iso, img  # downloadable here on top of --allowed-extensions
+//! user = alice:$2b$10$A.Jsw..zLa4W39bpYXLAQ.FjhWNsLSLQCQa9EeASUR80tMvgMjifG
+//! realm = Staff downloads
+//! ```
+//!
+//! `user` lines, one per account, require HTTP Basic credentials for the
+//! subtree; the part after the colon is a bcrypt hash of the password, as
+//! printed by `hdl_sv hash-password alice` or `htpasswd -nbB alice
+//! 'password'`. `realm` names them in the browser's login prompt, which
+//! otherwise shows the directory's path.
+//!
+//! A deeper file overrides `listing`, adds to `extensions` and, if it has
+//! `user` lines of its own, replaces the accounts. A file with a directive
+//! this server cannot enforce makes its subtree unavailable rather than
+//! silently serving it unprotected.
+
+use crate::ignore_rules::normalize;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+pub const ACCESS_FILE: &str = ".hdl_access";
+
+/// How long credentials that passed bcrypt are accepted without checking
+/// them again, so a listing full of thumbnails costs one verification
+/// rather than one per request.
+const VERIFIED_TTL: Duration = Duration::from_secs(300);
+/// Past this many remembered credentials, the expired ones are dropped,
+/// and if that is not enough, all of them.
+const MAX_VERIFIED: usize = 1024;
+
+/// Credentials that recently passed, keyed by a digest of the user, the
+/// stored hash and the password, so no password is kept in memory.
+type Verified = Mutex<HashMap<[u8; 32], Instant>>;
+
+pub struct AccessRules {
+    root: PathBuf,
+    /// Parsed access files keyed by path, reparsed when their mtime changes.
+    /// Only files that exist are kept, so requests for made-up paths cannot
+    /// grow the map.
+    files: Mutex<HashMap<PathBuf, Cached>>,
+    verified: Arc<Verified>,
+}
+
+struct Cached {
+    modified: SystemTime,
+    directives: Arc<Directives>,
+}
+
+#[derive(Default)]
+struct Directives {
+    listing: Option<bool>,
+    extensions: Vec<String>,
+    users: Vec<(String, String)>,
+    realm: Option<String>,
+    unsupported: bool,
+}
+
+/// The server-wide extension lists. `denied` is checked last and wins over
+/// both `allowed` and the extensions access files add.
+pub struct Extensions {
+    pub allowed: Vec<String>,
+    pub denied: Vec<String>,
+}
+
+impl Extensions {
+    fn is_denied(&self, file: &Path) -> bool {
+        let extension = file.extension().unwrap_or_default().to_string_lossy();
+        // Case-insensitive, since Windows serves secret.KEY for secret.key.
+        !extension.is_empty()
+            && self
+                .denied
+                .iter()
+                .any(|denied| denied.eq_ignore_ascii_case(&extension))
+    }
+}
+
+/// The effective rules for one directory.
+#[derive(Clone)]
+pub struct Access {
+    pub listing: bool,
+    pub extensions: Vec<String>,
+    /// Set when an access file on the way down could not be honoured.
+    pub denied: bool,
+    /// The accounts of the deepest access file that declares any.
+    pub auth: Option<Arc<Auth>>,
+}
+
+impl Access {
+    /// The realm of the accounts this directory needs credentials for, if
+    /// any.
+    pub fn realm(&self) -> Option<&str> {
+        self.auth.as_ref().map(|auth| auth.realm.as_str())
+    }
+
+    /// Whether a view may list this directory to a client let into
+    /// `realm`. Subtrees guarded by other accounts are left out, so a
+    /// listing of a parent never shows what lies behind their credentials.
+    pub fn can_list(&self, realm: Option<&str>) -> bool {
+        self.listing && !self.denied && self.realm() == realm
+    }
+
+    /// Applies these rules, as those of the directory holding `file`, on
+    /// top of the server-wide extension lists.
+    pub fn can_download(&self, file: &Path, extensions: &Extensions) -> bool {
+        !self.denied
+            && !extensions.is_denied(file)
+            && (crate::is_extension_allowed(file, &extensions.allowed)
+                || crate::is_extension_allowed(file, &self.extensions))
+    }
+}
+
+/// Accounts one access file lets into its subtree.
+pub struct Auth {
+    /// The directory the access file sits in, relative to the root, which
+    /// also tells apart the subtrees of different access files.
+    pub realm: String,
+    /// The realm the login prompt shows, from the `realm` directive.
+    pub name: String,
+    users: Vec<(String, String)>,
+    verified: Arc<Verified>,
+}
+
+/// The user and password of an Authorization header carrying Basic
+/// credentials.
+pub fn basic_credentials(header: Option<&str>) -> Option<(String, String)> {
+    let (scheme, credentials) = header?.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(credentials.trim())
+        .ok()?;
+    let (user, password) = std::str::from_utf8(&decoded).ok()?.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
+
+impl Auth {
+    /// Checks a user and password, from HTTP Basic credentials or an FTP
+    /// login, returning the user when they are valid for this subtree.
+    pub fn check(&self, user: &str, password: &str) -> Option<&str> {
+        let Some((name, hash)) = self.users.iter().find(|(name, _)| name == user) else {
+            // An unknown user still costs a verification, so timing does
+            // not tell which names exist.
+            if let Some((_, hash)) = self.users.first() {
+                let _ = bcrypt::verify(password, hash);
+            }
+            return None;
+        };
+
+        let key: [u8; 32] = Sha256::new()
+            .chain_update(name)
+            .chain_update([0])
+            .chain_update(hash)
+            .chain_update([0])
+            .chain_update(password)
+            .finalize()
+            .into();
+        let now = Instant::now();
+        if let Some(&verified) = self.verified.lock().unwrap().get(&key) {
+            if now.duration_since(verified) < VERIFIED_TTL {
+                return Some(name);
+            }
+        }
+        if !bcrypt::verify(password, hash).unwrap_or(false) {
+            return None;
+        }
+        let mut verified = self.verified.lock().unwrap();
+        if verified.len() >= MAX_VERIFIED {
+            verified.retain(|_, &mut at| now.duration_since(at) < VERIFIED_TTL);
+            if verified.len() >= MAX_VERIFIED {
+                verified.clear();
+            }
+        }
+        verified.insert(key, now);
+        Some(name)
+    }
+}
+
+impl AccessRules {
+    pub fn new(root: PathBuf) -> Self {
+        AccessRules {
+            root,
+            files: Mutex::new(HashMap::new()),
+            verified: Arc::default(),
+        }
+    }
+
+    /// Combines every access file from the root down to `directory`.
+    /// Resolved once per request; walks below it use [`Self::descend`].
+    pub fn resolve(&self, directory: &Path) -> Access {
+        let mut access = Access {
+            listing: true,
+            extensions: Vec::new(),
+            denied: false,
+            auth: None,
+        };
+        let relative = match directory.strip_prefix(&self.root) {
+            Ok(relative) => normalize(relative),
+            Err(_) => return access,
+        };
+
+        let mut current = self.root.clone();
+        self.apply(&mut access, &current);
+        for component in relative.components() {
+            current.push(component);
+            // Below a directory that does not exist there is nothing to find.
+            if !current.is_dir() {
+                break;
+            }
+            self.apply(&mut access, &current);
+        }
+        access
+    }
+
+    /// The rules for `path` itself if it is a directory, otherwise for the
+    /// directory holding it.
+    pub fn resolve_path(&self, path: &Path) -> Access {
+        if path.is_dir() {
+            self.resolve(path)
+        } else {
+            self.resolve(path.parent().unwrap_or(&self.root))
+        }
+    }
+
+    /// The rules for `directory`, a subdirectory of the one `parent` holds
+    /// the rules for, reading only its own access file.
+    pub fn descend(&self, parent: &Access, directory: &Path) -> Access {
+        let mut access = parent.clone();
+        self.apply(&mut access, directory);
+        access
+    }
+
+    /// Layers the access file in `directory`, if any, over `access`.
+    fn apply(&self, access: &mut Access, directory: &Path) {
+        let Some(directives) = self.directives(&directory.join(ACCESS_FILE)) else {
+            return;
+        };
+        access.listing = directives.listing.unwrap_or(access.listing);
+        access
+            .extensions
+            .extend(directives.extensions.iter().cloned());
+        access.denied |= directives.unsupported;
+        if !directives.users.is_empty() {
+            let realm = directory.strip_prefix(&self.root).unwrap_or(directory);
+            let realm = format!("/{}", realm.to_string_lossy());
+            access.auth = Some(Arc::new(Auth {
+                name: directives.realm.clone().unwrap_or_else(|| realm.clone()),
+                realm,
+                users: directives.users.clone(),
+                verified: Arc::clone(&self.verified),
+            }));
+        }
+    }
+
+    fn directives(&self, file: &Path) -> Option<Arc<Directives>> {
+        let modified = fs::metadata(file).and_then(|metadata| metadata.modified());
+        let mut files = self.files.lock().unwrap();
+        let Ok(modified) = modified else {
+            files.remove(file);
+            return None;
+        };
+        if let Some(cached) = files.get(file) {
+            if cached.modified == modified {
+                return Some(Arc::clone(&cached.directives));
+            }
+        }
+
+        let directives = Arc::new(parse(file));
+        files.insert(
+            file.to_path_buf(),
+            Cached {
+                modified,
+                directives: Arc::clone(&directives),
+            },
+        );
+        Some(directives)
+    }
+}
+
+fn parse(file: &Path) -> Directives {
+    let mut directives = Directives::default();
+    let contents = match fs::read_to_string(file) {
+        Ok(contents) => contents,
+        Err(e) => {
+            eprintln!("Error reading access file {:?}: {}", file, e);
+            directives.unsupported = true;
+            return directives;
+        }
+    };
+
+    for line in contents.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let parsed = line
+            .split_once('=')
+            .map(|(key, value)| (key.trim().to_ascii_lowercase(), value.trim()));
+        match parsed {
+            Some((key, "on")) if key == "listing" => directives.listing = Some(true),
+            Some((key, "off")) if key == "listing" => directives.listing = Some(false),
+            Some((key, value)) if key == "extensions" => directives.extensions.extend(
+                value
+                    .split(',')
+                    .map(|extension| extension.trim().to_string())
+                    .filter(|extension| !extension.is_empty()),
+            ),
+            Some((key, value)) if key == "realm" && !value.is_empty() => {
+                directives.realm = Some(value.to_string())
+            }
+            Some((key, value)) if key == "user" && parse_user(value).is_some() => {
+                directives.users.extend(parse_user(value))
+            }
+            _ => {
+                eprintln!(
+                    "Unsupported directive {:?} in {:?}; refusing access to {:?}",
+                    line,
+                    file,
+                    file.parent().unwrap_or(file)
+                );
+                directives.unsupported = true;
+            }
+        }
+    }
+    directives
+}
+
+/// Reads a `name:bcrypt-hash` account.
+fn parse_user(value: &str) -> Option<(String, String)> {
+    let (name, hash) = value.split_once(':')?;
+    let hash = hash.trim();
+    if name.trim().is_empty() || hash.parse::<bcrypt::HashParts>().is_err() {
+        return None;
+    }
+    Some((name.trim().to_string(), hash.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use tempfile::TempDir;
+
+    fn write(root: &Path, relative: &str, contents: &str) {
+        let path = root.join(relative);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    fn extensions() -> Extensions {
+        Extensions {
+            allowed: vec![String::from("txt")],
+            denied: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn deeper_files_layer_over_parents() {
+        let root = TempDir::new().unwrap();
+        write(root.path(), ACCESS_FILE, "extensions = iso # images\n");
+        write(
+            root.path(),
+            "a/.hdl_access",
+            "listing = off\nextensions = img, ,\n",
+        );
+        write(root.path(), "a/b/.hdl_access", "LISTING = on\n");
+        let rules = AccessRules::new(root.path().to_path_buf());
+
+        let a = rules.resolve(&root.path().join("a"));
+        assert!(!a.listing);
+        assert_eq!(a.extensions, ["iso", "img"]);
+        let b = rules.resolve(&root.path().join("a/b"));
+        assert!(b.listing);
+        assert_eq!(b.extensions, ["iso", "img"]);
+        assert!(b.can_download(Path::new("disk.img"), &extensions()));
+        assert!(!rules
+            .resolve(root.path())
+            .can_download(Path::new("disk.img"), &extensions()));
+    }
+
+    #[test]
+    fn unsupported_directive_denies_subtree() {
+        let root = TempDir::new().unwrap();
+        write(
+            root.path(),
+            "a/.hdl_access",
+            "listing = off\nrequire = mfa\n",
+        );
+        write(root.path(), "a/b/.hdl_access", "listing = on\n");
+        write(root.path(), "c/.hdl_access", "listing = maybe\n");
+        let rules = AccessRules::new(root.path().to_path_buf());
+
+        // A deeper file cannot lift the refusal.
+        let b = rules.resolve(&root.path().join("a/b"));
+        assert!(b.denied);
+        assert!(!b.can_list(None));
+        assert!(!b.can_download(Path::new("notes.txt"), &extensions()));
+        assert!(rules.resolve(&root.path().join("c")).denied);
+        assert!(!rules.resolve(root.path()).denied);
+    }
+
+    #[test]
+    fn dot_segments_cannot_skip_a_file() {
+        let root = TempDir::new().unwrap();
+        write(root.path(), "a/.hdl_access", "listing = off\n");
+        fs::create_dir_all(root.path().join("b")).unwrap();
+        let rules = AccessRules::new(root.path().to_path_buf());
+
+        assert!(!rules.resolve(&root.path().join("b/../a")).listing);
+        // A file is governed by the directory holding it.
+        assert!(!rules.resolve_path(&root.path().join("a/x.txt")).listing);
+    }
+
+    #[test]
+    fn descend_matches_resolve() {
+        let root = TempDir::new().unwrap();
+        write(root.path(), ACCESS_FILE, "extensions = iso\n");
+        write(root.path(), "a/.hdl_access", "listing = off\n");
+        write(root.path(), "a/b/.hdl_access", "extensions = img\n");
+        let rules = AccessRules::new(root.path().to_path_buf());
+
+        let a = rules.resolve(&root.path().join("a"));
+        let descended = rules.descend(&a, &root.path().join("a/b"));
+        let resolved = rules.resolve(&root.path().join("a/b"));
+        assert_eq!(descended.listing, resolved.listing);
+        assert_eq!(descended.extensions, resolved.extensions);
+        assert_eq!(descended.denied, resolved.denied);
+    }
+
+    #[test]
+    fn edited_files_are_reread() {
+        let root = TempDir::new().unwrap();
+        write(root.path(), ACCESS_FILE, "listing = off\n");
+        let rules = AccessRules::new(root.path().to_path_buf());
+        assert!(!rules.resolve(root.path()).listing);
+
+        write(root.path(), ACCESS_FILE, "listing = on\n");
+        // Make sure the edit shows in the mtime, whatever its resolution.
+        File::options()
+            .write(true)
+            .open(root.path().join(ACCESS_FILE))
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH)
+            .unwrap();
+        assert!(rules.resolve(root.path()).listing);
+
+        fs::remove_file(root.path().join(ACCESS_FILE)).unwrap();
+        assert!(rules.resolve(root.path()).listing);
+        assert!(rules.files.lock().unwrap().is_empty());
+    }
+
+    fn account(user: &str, password: &str) -> String {
+        // The lowest cost bcrypt allows keeps the tests fast.
+        format!("user = {}:{}\n", user, bcrypt::hash(password, 4).unwrap())
+    }
+
+    #[test]
+    fn basic_credentials_parse() {
+        assert_eq!(
+            basic_credentials(Some("Basic Ym9iOnB3OndpdGg6Y29sb25z")),
+            Some((String::from("bob"), String::from("pw:with:colons")))
+        );
+        assert_eq!(
+            basic_credentials(Some("  basic   Ym9iOg==  ")),
+            Some((String::from("bob"), String::new()))
+        );
+        assert_eq!(basic_credentials(Some("Bearer Ym9iOnB3")), None);
+        assert_eq!(basic_credentials(Some("Basic Ym9i")), None);
+        assert_eq!(basic_credentials(Some("Basic !!")), None);
+        assert_eq!(basic_credentials(None), None);
+    }
+
+    #[test]
+    fn accounts_guard_their_subtree() {
+        let root = TempDir::new().unwrap();
+        let accounts = account("alice", "secret") + &account("bob", "hunter2");
+        write(root.path(), "staff/.hdl_access", &accounts);
+        let rules = AccessRules::new(root.path().to_path_buf());
+
+        let access = rules.resolve_path(&root.path().join("staff/deep/report.txt"));
+        let auth = access.auth.as_ref().unwrap();
+        assert_eq!(auth.check("alice", "secret"), Some("alice"));
+        assert_eq!(auth.check("bob", "hunter2"), Some("bob"));
+        assert_eq!(auth.check("alice", "hunter2"), None);
+        assert_eq!(auth.check("carol", "secret"), None);
+        // Remembered credentials must still match exactly.
+        assert_eq!(auth.check("alice", "secret"), Some("alice"));
+        assert_eq!(auth.check("alice", "secret "), None);
+        assert!(rules.resolve(root.path()).auth.is_none());
+    }
+
+    #[test]
+    fn deeper_accounts_replace_parents() {
+        let root = TempDir::new().unwrap();
+        write(root.path(), "a/.hdl_access", &account("alice", "secret"));
+        let inner = account("bob", "hunter2") + "realm = Inner \"circle\"\n";
+        write(root.path(), "a/b/.hdl_access", &inner);
+        write(root.path(), "a/b/c/.hdl_access", "extensions = iso\n");
+        let rules = AccessRules::new(root.path().to_path_buf());
+
+        let a = rules.resolve(&root.path().join("a"));
+        let c = rules.resolve(&root.path().join("a/b/c"));
+        let auth = c.auth.as_ref().unwrap();
+        assert_eq!(auth.check("alice", "secret"), None);
+        assert_eq!(auth.check("bob", "hunter2"), Some("bob"));
+        assert_eq!(a.realm(), Some("/a"));
+        assert_eq!(c.realm(), Some("/a/b"));
+        assert_eq!(auth.name, "Inner \"circle\"");
+        assert_eq!(a.auth.as_ref().unwrap().name, "/a");
+
+        // Views list a protected directory only to its own realm.
+        assert!(a.can_list(Some("/a")));
+        assert!(!a.can_list(None));
+        assert!(!c.can_list(Some("/a")));
+        assert!(!rules.resolve(root.path()).can_list(Some("/a")));
+    }
+
+    #[test]
+    fn malformed_accounts_deny_subtree() {
+        let root = TempDir::new().unwrap();
+        write(root.path(), "a/.hdl_access", "user = alice:plaintext\n");
+        write(root.path(), "b/.hdl_access", "user = :$2b$04$abc\n");
+        let rules = AccessRules::new(root.path().to_path_buf());
+
+        // Serving the subtree unprotected would be worse than not at all.
+        assert!(rules.resolve(&root.path().join("a")).denied);
+        assert!(rules.resolve(&root.path().join("b")).denied);
+    }
+
+    #[test]
+    fn denied_extensions_win() {
+        let root = TempDir::new().unwrap();
+        write(root.path(), ACCESS_FILE, "extensions = key, iso\n");
+        let access = AccessRules::new(root.path().to_path_buf()).resolve(root.path());
+        let extensions = Extensions {
+            allowed: vec![String::from("*")],
+            denied: vec![String::from("key"), String::from("pem")],
+        };
+
+        assert!(access.can_download(Path::new("disk.iso"), &extensions));
+        // `*` covers files without an extension too.
+        assert!(access.can_download(Path::new("README"), &extensions));
+        for file in ["server.key", "server.KEY", "chain.Pem", "dir/x.pem"] {
+            assert!(
+                !access.can_download(Path::new(file), &extensions),
+                "{}",
+                file
+            );
+        }
+        // Only the last extension counts.
+        assert!(access.can_download(Path::new("server.key.txt"), &extensions));
+    }
+}
@@ -0,0 +1,286 @@
+/*
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ * More licensing information can be found in the project LICENSE file
+ * Author: Harshit Jain
+ * Email: reach@harsh1998.dev
+ */
+
+//! What every listener checks before taking on a client: the
+//! `--max-connections` ceiling, bans and `--rate-limit`, shared so the FTP
+//! and TFTP listeners cannot be used to get around them. The country rules
+//! live here too, for the listeners that have no request to answer with a
+//! page, and so does the `--login-rate` throttle, which HTTP and FTP logins
+//! count against alike.
+
+use crate::access::Auth;
+use crate::bans::Bans;
+use crate::geoip::GeoIp;
+use crate::rate_limit::RateLimiter;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub struct Admission {
+    max_connections: Option<usize>,
+    active: Arc<AtomicUsize>,
+    pub bans: Option<Bans>,
+    pub rate_limiter: Option<RateLimiter>,
+    pub geoip: Option<GeoIp>,
+    /// Failed logins per address, for `--login-rate`.
+    login_failures: RateLimiter,
+}
+
+/// Why a client was turned away.
+pub enum Refusal {
+    Overloaded,
+    Banned,
+    /// Over `--rate-limit`; the next request would pass after this long.
+    RateLimited(Duration),
+}
+
+/// The outcome of checking credentials against an access file's accounts.
+pub enum Login<'a> {
+    /// The credentials belong to this user.
+    Accepted(&'a str),
+    /// None were given, or they are wrong.
+    Rejected,
+    /// Too many recent failures from the address; nothing was checked, and
+    /// the next attempt would be after this long.
+    Throttled(Duration),
+}
+
+impl Admission {
+    pub fn new(
+        max_connections: Option<usize>,
+        bans: Option<Bans>,
+        rate_limiter: Option<RateLimiter>,
+        geoip: Option<GeoIp>,
+        login_failures: RateLimiter,
+    ) -> Self {
+        Admission {
+            max_connections,
+            active: Arc::default(),
+            bans,
+            rate_limiter,
+            geoip,
+            login_failures,
+        }
+    }
+
+    /// Takes on a client from `address`, counting it as in flight for as
+    /// long as the returned guard lives. A client refused by the rate limit
+    /// gets a strike towards `--ban-after`.
+    pub fn admit(&self, address: Option<IpAddr>) -> Result<Connection, Refusal> {
+        // The slot is claimed before anything else is checked, so listeners
+        // admitting at the same moment cannot overshoot the ceiling. A
+        // refusal below hands it back as the guard drops.
+        self.active
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |active| {
+                match self.max_connections {
+                    Some(max_connections) if active >= max_connections => None,
+                    _ => Some(active + 1),
+                }
+            })
+            .map_err(|_| Refusal::Overloaded)?;
+        let connection = Connection(Arc::clone(&self.active));
+        if let Some(address) = address {
+            if let Some(bans) = &self.bans {
+                if bans.is_banned(address) {
+                    return Err(Refusal::Banned);
+                }
+            }
+            if let Some(rate_limiter) = &self.rate_limiter {
+                if let Err(retry_after) = rate_limiter.check(address) {
+                    if self.bans.as_ref().is_some_and(|bans| bans.strike(address)) {
+                        println!("Banned {} for exceeding the rate limit", address);
+                    }
+                    return Err(Refusal::RateLimited(retry_after));
+                }
+            }
+        }
+        Ok(connection)
+    }
+
+    /// Checks `credentials` (user and password) from `address` against
+    /// `auth`. An address past `--login-burst` failures is throttled before
+    /// any password is checked, so guessing costs no bcrypt work, and every
+    /// failure is a strike towards `--ban-after`.
+    pub fn login<'a>(
+        &self,
+        auth: &'a Auth,
+        address: Option<IpAddr>,
+        credentials: Option<(&str, &str)>,
+    ) -> Login<'a> {
+        let Some((user, password)) = credentials else {
+            return Login::Rejected;
+        };
+        if let Some(retry_after) = address.and_then(|address| self.login_failures.wait(address)) {
+            return Login::Throttled(retry_after);
+        }
+        if let Some(user) = auth.check(user, password) {
+            return Login::Accepted(user);
+        }
+        if let Some(address) = address {
+            let _ = self.login_failures.check(address);
+            if self.bans.as_ref().is_some_and(|bans| bans.strike(address)) {
+                println!("Banned {} for failed logins to {}", address, auth.realm);
+            }
+        }
+        Login::Rejected
+    }
+
+    /// Whether the country rules let `address` in; always, without
+    /// `--geoip-db`.
+    pub fn is_country_allowed(&self, address: IpAddr) -> bool {
+        self.geoip
+            .as_ref()
+            .is_none_or(|geoip| geoip.is_allowed(geoip.country(address).as_deref()))
+    }
+
+    /// Connections in flight, on every listener.
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+}
+
+/// Counts a connection as in flight for as long as it is alive.
+pub struct Connection(Arc<AtomicUsize>);
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::access::AccessRules;
+    use std::sync::Barrier;
+    use std::thread;
+
+    fn address() -> Option<IpAddr> {
+        Some(IpAddr::from([192, 0, 2, 1]))
+    }
+
+    fn login_failures() -> RateLimiter {
+        RateLimiter::new(5, 10, Vec::new())
+    }
+
+    #[test]
+    fn ceiling_holds_under_contention() {
+        let admission = Arc::new(Admission::new(Some(4), None, None, None, login_failures()));
+        let barrier = Arc::new(Barrier::new(32));
+        let threads: Vec<_> = (0..32)
+            .map(|_| {
+                let admission = Arc::clone(&admission);
+                let barrier = Arc::clone(&barrier);
+                thread::spawn(move || {
+                    barrier.wait();
+                    let connection = admission.admit(address()).ok();
+                    // Keep the slot until every thread has tried.
+                    barrier.wait();
+                    connection.is_some()
+                })
+            })
+            .collect();
+        let admitted = threads
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .filter(|&admitted| admitted)
+            .count();
+        assert_eq!(admitted, 4);
+        assert_eq!(admission.active(), 0);
+    }
+
+    #[test]
+    fn refusals_release_their_slot() {
+        let bans = Bans::new(Some(1), Duration::from_secs(60));
+        bans.ban(address().unwrap());
+        let admission = Admission::new(Some(1), Some(bans), None, None, login_failures());
+        assert!(matches!(admission.admit(address()), Err(Refusal::Banned)));
+        assert_eq!(admission.active(), 0);
+        let _connection = admission
+            .admit(Some(IpAddr::from([192, 0, 2, 2])))
+            .ok()
+            .unwrap();
+        assert_eq!(admission.active(), 1);
+        assert!(matches!(admission.admit(None), Err(Refusal::Overloaded)));
+    }
+
+    #[test]
+    fn rate_limited_clients_release_their_slot() {
+        let admission = Admission::new(
+            Some(1),
+            None,
+            Some(RateLimiter::new(60, 1, Vec::new())),
+            None,
+            login_failures(),
+        );
+        drop(admission.admit(address()).ok().unwrap());
+        assert!(matches!(
+            admission.admit(address()),
+            Err(Refusal::RateLimited(_))
+        ));
+        assert_eq!(admission.active(), 0);
+    }
+
+    #[test]
+    fn failed_logins_are_throttled_and_struck() {
+        let root = tempfile::tempdir().unwrap();
+        let hash = bcrypt::hash("secret", 4).unwrap();
+        std::fs::write(
+            root.path().join(crate::access::ACCESS_FILE),
+            format!("user = alice:{}\n", hash),
+        )
+        .unwrap();
+        let access = AccessRules::new(root.path().to_path_buf()).resolve(root.path());
+        let auth = access.auth.unwrap();
+        let bans = Bans::new(Some(2), Duration::from_secs(60));
+        let admission = Admission::new(
+            None,
+            Some(bans),
+            None,
+            None,
+            RateLimiter::new(1, 2, Vec::new()),
+        );
+        let is_banned = |address: Option<IpAddr>| {
+            let bans = admission.bans.as_ref().unwrap();
+            bans.is_banned(address.unwrap())
+        };
+
+        // Asking without credentials is how every login starts, so it is
+        // neither a failure nor a strike.
+        for _ in 0..5 {
+            assert!(matches!(
+                admission.login(&auth, address(), None),
+                Login::Rejected
+            ));
+        }
+        assert!(!is_banned(address()));
+
+        let wrong = Some(("alice", "guess"));
+        assert!(matches!(
+            admission.login(&auth, address(), wrong),
+            Login::Rejected
+        ));
+        assert!(matches!(
+            admission.login(&auth, address(), wrong),
+            Login::Rejected
+        ));
+        assert!(is_banned(address()));
+        // Past the burst even the right password is not checked.
+        let right = Some(("alice", "secret"));
+        assert!(matches!(
+            admission.login(&auth, address(), right),
+            Login::Throttled(_)
+        ));
+        let other = Some(IpAddr::from([192, 0, 2, 2]));
+        assert!(matches!(
+            admission.login(&auth, other, right),
+            Login::Accepted("alice")
+        ));
+        assert!(!is_banned(other));
+    }
+}
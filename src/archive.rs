@@ -0,0 +1,153 @@
+/*
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ * More licensing information can be found in the project LICENSE file
+ * Author: Harshit Jain
+ * Email: reach@harsh1998.dev
+ */
+
+//! Read-only access to the members of zip and tar archives, so a single
+//! file can be fetched without downloading or extracting the whole archive.
+
+use flate2::read::GzDecoder;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+/// Listings stop after this many members.
+pub const MAX_MEMBERS: usize = 10_000;
+
+#[derive(Clone, Copy)]
+pub enum Format {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl Format {
+    pub fn of(path: &Path) -> Option<Format> {
+        let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+        if name.ends_with(".zip") {
+            Some(Format::Zip)
+        } else if name.ends_with(".tar") {
+            Some(Format::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Format::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+pub struct Member {
+    pub name: String,
+    pub size: u64,
+    pub is_dir: bool,
+}
+
+/// Lists up to [`MAX_MEMBERS`] members in archive order, along with whether
+/// any were left out.
+pub fn list(path: &Path, format: Format) -> io::Result<(Vec<Member>, bool)> {
+    match format {
+        Format::Zip => {
+            let mut archive = ZipArchive::new(BufReader::new(File::open(path)?))?;
+            let mut members = Vec::new();
+            for index in 0..archive.len().min(MAX_MEMBERS) {
+                // Raw access reads the header only, without setting up decompression.
+                let member = archive.by_index_raw(index)?;
+                members.push(Member {
+                    name: member.name().to_string(),
+                    size: member.size(),
+                    is_dir: member.is_dir(),
+                });
+            }
+            Ok((members, archive.len() > MAX_MEMBERS))
+        }
+        // A plain tar is walked by seeking over member data; a compressed
+        // one has to be decompressed up to the last listed member.
+        Format::Tar => list_tar(tar::Archive::new(File::open(path)?).entries_with_seek()?),
+        Format::TarGz => list_tar(tar_gz(path)?.entries()?),
+    }
+}
+
+/// Finds the file member called `name` and hands `serve` a reader over its
+/// decompressed contents together with its size. Returns `Ok(None)` when
+/// the archive holds no such file.
+pub fn with_member<T>(
+    path: &Path,
+    format: Format,
+    name: &str,
+    serve: impl FnOnce(&mut dyn Read, u64) -> T,
+) -> io::Result<Option<T>> {
+    match format {
+        Format::Zip => {
+            let mut archive = ZipArchive::new(BufReader::new(File::open(path)?))?;
+            let mut member = match archive.by_name(name) {
+                Ok(member) if member.is_file() => member,
+                Ok(_) | Err(ZipError::FileNotFound) => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+            let size = member.size();
+            Ok(Some(serve(&mut member, size)))
+        }
+        Format::Tar => find_tar_member(
+            tar::Archive::new(File::open(path)?).entries_with_seek()?,
+            name,
+            serve,
+        ),
+        Format::TarGz => find_tar_member(tar_gz(path)?.entries()?, name, serve),
+    }
+}
+
+fn tar_gz(path: &Path) -> io::Result<tar::Archive<GzDecoder<BufReader<File>>>> {
+    Ok(tar::Archive::new(GzDecoder::new(BufReader::new(
+        File::open(path)?,
+    ))))
+}
+
+fn list_tar<R: Read>(entries: tar::Entries<'_, R>) -> io::Result<(Vec<Member>, bool)> {
+    let mut members = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let name = tar_member_name(&entry);
+        // The `./` entry for the archive root has nothing to show.
+        if name.is_empty() {
+            continue;
+        }
+        if members.len() == MAX_MEMBERS {
+            return Ok((members, true));
+        }
+        members.push(Member {
+            name,
+            size: entry.header().size()?,
+            is_dir: entry.header().entry_type().is_dir(),
+        });
+    }
+    Ok((members, false))
+}
+
+fn find_tar_member<R: Read, T>(
+    entries: tar::Entries<'_, R>,
+    name: &str,
+    serve: impl FnOnce(&mut dyn Read, u64) -> T,
+) -> io::Result<Option<T>> {
+    for entry in entries {
+        let mut entry = entry?;
+        if entry.header().entry_type().is_file() && tar_member_name(&entry) == name {
+            let size = entry.header().size()?;
+            return Ok(Some(serve(&mut entry, size)));
+        }
+    }
+    Ok(None)
+}
+
+/// Archives made with `tar -C dir .` prefix every name with `./`, which is
+/// dropped so member names read the same as in a zip.
+fn tar_member_name<R: Read>(entry: &tar::Entry<'_, R>) -> String {
+    let name = String::from_utf8_lossy(&entry.path_bytes()).into_owned();
+    match name.strip_prefix("./") {
+        Some(stripped) => stripped.to_string(),
+        None => name,
+    }
+}
@@ -0,0 +1,199 @@
+/*
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ * More licensing information can be found in the project LICENSE file
+ * Author: Harshit Jain
+ * Email: reach@harsh1998.dev
+ */
+
+//! The `--audit-log`: one JSON line per file transfer or deletion, kept
+//! apart from the console output so it can be retained and shipped on its
+//! own, and rotated once it grows past a size.
+
+use chrono::Utc;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub struct AuditLog {
+    path: PathBuf,
+    max_size: u64,
+    /// Rotated files kept beside the live one, as `<path>.1` (the newest)
+    /// up to `<path>.<keep>`.
+    keep: u32,
+    file: Mutex<LogFile>,
+}
+
+struct LogFile {
+    file: File,
+    size: u64,
+}
+
+impl AuditLog {
+    pub fn open(path: &Path, max_size: u64, keep: u32) -> io::Result<Self> {
+        Ok(AuditLog {
+            path: path.to_path_buf(),
+            max_size,
+            keep,
+            file: Mutex::new(open_append(path)?),
+        })
+    }
+
+    fn append(&self, line: &str) -> io::Result<()> {
+        let mut log = self.file.lock().unwrap();
+        if log.size > 0 && log.size + line.len() as u64 > self.max_size {
+            *log = self.rotate()?;
+        }
+        log.file.write_all(line.as_bytes())?;
+        log.size += line.len() as u64;
+        Ok(())
+    }
+
+    /// Shifts every rotated file up by one, dropping the oldest, and starts
+    /// a fresh live file.
+    fn rotate(&self) -> io::Result<LogFile> {
+        let rotated = |index: u32| PathBuf::from(format!("{}.{}", self.path.display(), index));
+        for index in (1..self.keep).rev() {
+            match fs::rename(rotated(index), rotated(index + 1)) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        if self.keep > 0 {
+            fs::rename(&self.path, rotated(1))?;
+        } else {
+            fs::remove_file(&self.path)?;
+        }
+        open_append(&self.path)
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<LogFile> {
+    let file = File::options().create(true).append(true).open(path)?;
+    let size = file.metadata()?.len();
+    Ok(LogFile { file, size })
+}
+
+/// What the transfers answering one request are recorded under.
+pub struct Recorder {
+    log: Arc<AuditLog>,
+    client: String,
+    /// The client's country code, with --geoip-db.
+    country: Option<String>,
+    /// Who the transfers were authorized for, under an access file that
+    /// asks for credentials.
+    user: Option<String>,
+    path: String,
+}
+
+impl Recorder {
+    pub fn new(log: &Arc<AuditLog>, client: String, country: Option<String>, path: String) -> Self {
+        Recorder {
+            log: Arc::clone(log),
+            client,
+            country,
+            user: None,
+            path,
+        }
+    }
+
+    pub fn set_user(&mut self, user: &str) {
+        self.user = Some(user.to_string());
+    }
+
+    /// Appends one transfer. `ranges` is empty for a whole file, and
+    /// `length` is what the response announced, if anything.
+    pub fn record(
+        &self,
+        ranges: &str,
+        bytes_sent: u64,
+        length: Option<u64>,
+        duration: Duration,
+        outcome: &str,
+    ) {
+        let json_or_null = |value: &str| {
+            if value.is_empty() {
+                String::from("null")
+            } else {
+                format!("\"{}\"", crate::escape_json(value))
+            }
+        };
+        let line = format!(
+            "{{\"time\":\"{}\",\"client\":\"{}\",\"country\":{},\"user\":{},\"path\":\"{}\",\"ranges\":{},\"bytes\":{},\"length\":{},\"duration_ms\":{},\"outcome\":\"{}\"}}\n",
+            Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ"),
+            crate::escape_json(&self.client),
+            json_or_null(self.country.as_deref().unwrap_or_default()),
+            json_or_null(self.user.as_deref().unwrap_or_default()),
+            crate::escape_json(&self.path),
+            json_or_null(ranges),
+            bytes_sent,
+            length.map_or_else(|| String::from("null"), |length| length.to_string()),
+            duration.as_millis(),
+            outcome
+        );
+        if let Err(e) = self.log.append(&line) {
+            eprintln!("Error writing audit log {:?}: {}", self.log.path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(path: &Path, suffix: &str) -> Option<String> {
+        fs::read_to_string(format!("{}{}", path.display(), suffix)).ok()
+    }
+
+    #[test]
+    fn rotation_keeps_the_newest_files() {
+        let scratch = tempfile::tempdir().unwrap();
+        let path = scratch.path().join("audit.log");
+        let log = AuditLog::open(&path, 12, 2).unwrap();
+
+        log.append("aaaaaaaa\n").unwrap();
+        log.append("b\n").unwrap();
+        assert_eq!(read(&path, "").as_deref(), Some("aaaaaaaa\nb\n"));
+        assert_eq!(read(&path, ".1"), None);
+
+        log.append("cccccccc\n").unwrap();
+        log.append("dddddddd\n").unwrap();
+        assert_eq!(read(&path, "").as_deref(), Some("dddddddd\n"));
+        assert_eq!(read(&path, ".1").as_deref(), Some("cccccccc\n"));
+        assert_eq!(read(&path, ".2").as_deref(), Some("aaaaaaaa\nb\n"));
+
+        log.append("eeeeeeee\n").unwrap();
+        assert_eq!(read(&path, ".1").as_deref(), Some("dddddddd\n"));
+        assert_eq!(read(&path, ".2").as_deref(), Some("cccccccc\n"));
+        assert_eq!(read(&path, ".3"), None);
+    }
+
+    #[test]
+    fn oversized_lines_still_land() {
+        let scratch = tempfile::tempdir().unwrap();
+        let path = scratch.path().join("audit.log");
+        let log = AuditLog::open(&path, 4, 1).unwrap();
+
+        log.append("longer than the limit\n").unwrap();
+        assert_eq!(read(&path, ".1"), None);
+        log.append("again\n").unwrap();
+        assert_eq!(read(&path, "").as_deref(), Some("again\n"));
+        assert_eq!(
+            read(&path, ".1").as_deref(),
+            Some("longer than the limit\n")
+        );
+    }
+
+    #[test]
+    fn keeping_none_truncates() {
+        let scratch = tempfile::tempdir().unwrap();
+        let path = scratch.path().join("audit.log");
+        fs::write(&path, "from before\n").unwrap();
+        let log = AuditLog::open(&path, 16, 0).unwrap();
+
+        log.append("next\n").unwrap();
+        assert_eq!(read(&path, "").as_deref(), Some("next\n"));
+        assert_eq!(fs::read_dir(scratch.path()).unwrap().count(), 1);
+    }
+}
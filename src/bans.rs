@@ -0,0 +1,167 @@
+/*
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ * More licensing information can be found in the project LICENSE file
+ * Author: Harshit Jain
+ * Email: reach@harsh1998.dev
+ */
+
+//! Temporary bans (`--ban-after`) for clients that keep probing for files
+//! outside the served root, or that ask for a `--honeypot` path at all.
+//! Banned addresses are turned away as soon as they connect, before a
+//! thread is spent on them.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub struct Bans {
+    /// Strikes that earn a ban, each within `duration` of the last; `None`
+    /// when only honeypots ban.
+    limit: Option<u32>,
+    duration: Duration,
+    clients: Mutex<HashMap<IpAddr, Record>>,
+}
+
+struct Record {
+    strikes: u32,
+    last_strike: Instant,
+    banned_until: Option<Instant>,
+}
+
+impl Bans {
+    pub fn new(limit: Option<u32>, duration: Duration) -> Self {
+        Bans {
+            limit,
+            duration,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_banned(&self, address: IpAddr) -> bool {
+        self.clients
+            .lock()
+            .unwrap()
+            .get(&address)
+            .and_then(|record| record.banned_until)
+            .is_some_and(|until| Instant::now() < until)
+    }
+
+    /// Counts one strike against `address`. Returns whether it earned the
+    /// client a ban.
+    pub fn strike(&self, address: IpAddr) -> bool {
+        let Some(limit) = self.limit else {
+            return false;
+        };
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap();
+        let record = Self::record(&mut clients, address, now, self.duration);
+        if now.duration_since(record.last_strike) >= self.duration {
+            record.strikes = 0;
+        }
+        record.strikes += 1;
+        record.last_strike = now;
+        if record.strikes < limit {
+            return false;
+        }
+        record.strikes = 0;
+        record.banned_until = Some(now + self.duration);
+        true
+    }
+
+    /// Bans `address` straight away, as a honeypot request does.
+    pub fn ban(&self, address: IpAddr) {
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap();
+        let record = Self::record(&mut clients, address, now, self.duration);
+        record.strikes = 0;
+        record.banned_until = Some(now + self.duration);
+    }
+
+    fn record(
+        clients: &mut HashMap<IpAddr, Record>,
+        address: IpAddr,
+        now: Instant,
+        duration: Duration,
+    ) -> &mut Record {
+        // Strikes and bans are rare, so expired records are dropped on every
+        // one, which keeps the table from growing without bound.
+        clients.retain(|_, record| {
+            now.duration_since(record.last_strike) < duration
+                || record.banned_until.is_some_and(|until| now < until)
+        });
+        clients.entry(address).or_insert(Record {
+            strikes: 0,
+            last_strike: now,
+            banned_until: None,
+        })
+    }
+}
+
+/// The `--honeypot` path that `path` asks for, itself or anything below it.
+pub fn honeypot<'a>(honeypots: &'a [String], path: &str) -> Option<&'a str> {
+    honeypots.iter().map(String::as_str).find(|honeypot| {
+        path.strip_prefix(honeypot)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use std::thread;
+
+    const CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const OTHER: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+    #[test]
+    fn strikes_earn_a_ban() {
+        let bans = Bans::new(Some(3), Duration::from_secs(60));
+        assert!(!bans.strike(CLIENT));
+        assert!(!bans.strike(CLIENT));
+        assert!(!bans.strike(OTHER));
+        assert!(!bans.is_banned(CLIENT));
+        assert!(bans.strike(CLIENT));
+        assert!(bans.is_banned(CLIENT));
+        assert!(!bans.is_banned(OTHER));
+    }
+
+    #[test]
+    fn strikes_and_bans_expire() {
+        let duration = Duration::from_millis(100);
+        let bans = Bans::new(Some(2), duration);
+        assert!(!bans.strike(CLIENT));
+        thread::sleep(duration);
+        // The first strike is too old to count towards the second.
+        assert!(!bans.strike(CLIENT));
+        assert!(bans.strike(CLIENT));
+        assert!(bans.is_banned(CLIENT));
+        thread::sleep(duration);
+        assert!(!bans.is_banned(CLIENT));
+        // Expired records are dropped on the next strike.
+        bans.strike(OTHER);
+        assert_eq!(bans.clients.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn honeypots_ban_at_once() {
+        let honeypots = [String::from("/wp-admin"), String::from("/.env")];
+        assert_eq!(honeypot(&honeypots, "/wp-admin"), Some("/wp-admin"));
+        assert_eq!(
+            honeypot(&honeypots, "/wp-admin/setup.php"),
+            Some("/wp-admin")
+        );
+        assert_eq!(honeypot(&honeypots, "/.env"), Some("/.env"));
+        // Only whole path segments match.
+        assert_eq!(honeypot(&honeypots, "/wp-administrator"), None);
+        assert_eq!(honeypot(&honeypots, "/docs/wp-admin"), None);
+
+        // Without --ban-after strikes never ban, but a honeypot still does.
+        let bans = Bans::new(None, Duration::from_secs(60));
+        assert!(!bans.strike(CLIENT));
+        bans.ban(CLIENT);
+        assert!(bans.is_banned(CLIENT));
+        assert!(!bans.is_banned(OTHER));
+    }
+}
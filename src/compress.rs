@@ -0,0 +1,239 @@
+/*
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ * More licensing information can be found in the project LICENSE file
+ * Author: Harshit Jain
+ * Email: reach@harsh1998.dev
+ */
+
+//! `Content-Encoding` for generated responses such as directory listings
+//! and JSON views, and for whole-file downloads of text such as logs or
+//! SQL dumps, negotiated against the client's `Accept-Encoding`. Brotli
+//! and zstd are only available in builds with the cargo feature of the
+//! same name.
+
+use std::io::{self, Read};
+
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Encoding {
+    /// Brotli, smaller than gzip for text (needs the `brotli` feature).
+    Br,
+    Gzip,
+    /// Zstandard, close to brotli in size and much faster on large files (needs the `zstd` feature).
+    Zstd,
+}
+
+impl Encoding {
+    /// The token used in `Accept-Encoding` and `Content-Encoding`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Br => "br",
+            Encoding::Gzip => "gzip",
+            Encoding::Zstd => "zstd",
+        }
+    }
+
+    /// The cargo feature this build would need to produce the encoding,
+    /// if it was left out.
+    pub fn missing_feature(self) -> Option<&'static str> {
+        match self {
+            Encoding::Br => (!cfg!(feature = "brotli")).then_some("brotli"),
+            Encoding::Gzip => None,
+            Encoding::Zstd => (!cfg!(feature = "zstd")).then_some("zstd"),
+        }
+    }
+
+    /// Wraps `source` in a reader that yields its bytes compressed.
+    fn encoder<'a>(self, source: impl Read + 'a, level: u32) -> io::Result<Box<dyn Read + 'a>> {
+        match self {
+            #[cfg(feature = "brotli")]
+            Encoding::Br => {
+                // 22 is the window size brotli itself defaults to.
+                Ok(Box::new(brotli::CompressorReader::new(
+                    source, 4096, level, 22,
+                )))
+            }
+            #[cfg(feature = "zstd")]
+            Encoding::Zstd => Ok(Box::new(zstd::stream::read::Encoder::new(
+                source,
+                level as i32,
+            )?)),
+            Encoding::Gzip => Ok(Box::new(flate2::read::GzEncoder::new(
+                source,
+                flate2::Compression::new(level),
+            ))),
+            #[allow(unreachable_patterns)]
+            encoding => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("built without the {} encoding", encoding.name()),
+            )),
+        }
+    }
+}
+
+pub struct Settings {
+    /// Offered encodings, most preferred first. Empty turns compression off.
+    pub encodings: Vec<Encoding>,
+    pub level: u32,
+    /// Bodies smaller than this are sent as they are, since the encoding
+    /// overhead would eat most of the saving.
+    pub min_size: usize,
+    /// Media types worth compressing, either exact (`application/json`) or
+    /// a whole top-level type (`text/*`).
+    pub types: Vec<String>,
+}
+
+impl Settings {
+    /// Whether responses of `content_type` may be compressed at all, and so
+    /// have to be sent with `Vary: Accept-Encoding` either way.
+    pub fn applies_to(&self, content_type: &str) -> bool {
+        let essence = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        !self.encodings.is_empty()
+            && self
+                .types
+                .iter()
+                .any(|pattern| match pattern.strip_suffix("/*") {
+                    Some(top_level) => essence
+                        .split_once('/')
+                        .is_some_and(|(essence_top, _)| essence_top == top_level),
+                    None => *pattern == essence,
+                })
+    }
+
+    /// Picks the first offered encoding the client accepts, weighing the
+    /// `q` values of `accept_encoding` (RFC 9110 section 12.5.3).
+    pub fn negotiate(&self, accept_encoding: Option<&str>) -> Option<Encoding> {
+        let accept_encoding = accept_encoding?;
+        let mut wildcard = None;
+        let mut listed = Vec::new();
+        for item in accept_encoding.split(',') {
+            let mut params = item.split(';');
+            let coding = params
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_ascii_lowercase();
+            let quality = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if coding == "*" {
+                wildcard = Some(quality);
+            } else if !coding.is_empty() {
+                listed.push((coding, quality));
+            }
+        }
+        self.encodings.iter().copied().find(|encoding| {
+            let quality = listed
+                .iter()
+                .find(|(coding, _)| coding == encoding.name())
+                .map(|(_, quality)| *quality)
+                .or(wildcard)
+                .unwrap_or(0.0);
+            quality > 0.0
+        })
+    }
+
+    /// Compresses `body` with `encoding`, or returns `None` when it is too
+    /// small to bother or would not get any smaller.
+    pub fn compress(&self, encoding: Encoding, body: &[u8]) -> Option<Vec<u8>> {
+        if body.len() < self.min_size {
+            return None;
+        }
+        let mut compressed = Vec::with_capacity(body.len() / 2);
+        match self
+            .encoder(encoding, body)
+            .and_then(|mut encoder| encoder.read_to_end(&mut compressed))
+        {
+            Ok(_) if compressed.len() < body.len() => Some(compressed),
+            Ok(_) => None,
+            Err(e) => {
+                eprintln!("Error compressing response with {}: {}", encoding.name(), e);
+                None
+            }
+        }
+    }
+
+    /// Streams `source` through `encoding`, for bodies too large to hold
+    /// in memory. Unlike `compress`, the size threshold is the caller's to
+    /// check.
+    pub fn encoder<'a>(
+        &self,
+        encoding: Encoding,
+        source: impl Read + 'a,
+    ) -> io::Result<Box<dyn Read + 'a>> {
+        encoding.encoder(source, self.level)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(encodings: Vec<Encoding>) -> Settings {
+        Settings {
+            encodings,
+            level: 6,
+            min_size: 64,
+            types: vec![String::from("text/*"), String::from("application/json")],
+        }
+    }
+
+    fn settings_off() -> Settings {
+        settings(Vec::new())
+    }
+
+    #[test]
+    fn server_preference_wins_among_accepted() {
+        let settings = settings(vec![Encoding::Zstd, Encoding::Gzip]);
+        let negotiate = |accept| settings.negotiate(accept).map(Encoding::name);
+        assert_eq!(negotiate(Some("gzip, zstd")), Some("zstd"));
+        assert_eq!(negotiate(Some("GZIP;q=0.1")), Some("gzip"));
+        assert_eq!(negotiate(Some("zstd;q=0, gzip")), Some("gzip"));
+        assert_eq!(negotiate(Some("*")), Some("zstd"));
+        assert_eq!(negotiate(Some("*;q=0, gzip")), Some("gzip"));
+        // An explicit entry overrides the wildcard.
+        assert_eq!(negotiate(Some("zstd;q=0, *")), Some("gzip"));
+        for refused in [None, Some(""), Some("identity"), Some("br, deflate")] {
+            assert_eq!(negotiate(refused), None);
+        }
+        assert_eq!(
+            settings_off().negotiate(Some("gzip")).map(Encoding::name),
+            None
+        );
+    }
+
+    #[test]
+    fn only_listed_types_apply() {
+        let settings = settings(vec![Encoding::Gzip]);
+        assert!(settings.applies_to("text/html; charset=utf-8"));
+        assert!(settings.applies_to("Application/JSON"));
+        assert!(!settings.applies_to("application/json-seq"));
+        assert!(!settings.applies_to("image/png"));
+        assert!(!settings.applies_to("textual/plain"));
+        assert!(!settings_off().applies_to("text/plain"));
+    }
+
+    #[test]
+    fn compresses_only_when_it_pays() {
+        let settings = settings(vec![Encoding::Gzip]);
+        let body = "listing entry\n".repeat(100);
+        let compressed = settings.compress(Encoding::Gzip, body.as_bytes()).unwrap();
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(compressed.as_slice())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, body);
+
+        assert_eq!(settings.compress(Encoding::Gzip, b"short"), None);
+        // Bytes with no redundancy only grow by the gzip framing.
+        let noise: Vec<u8> = (0u32..256)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        assert_eq!(settings.compress(Encoding::Gzip, &noise), None);
+    }
+}
@@ -0,0 +1,134 @@
+/*
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ * More licensing information can be found in the project LICENSE file
+ * Author: Harshit Jain
+ * Email: reach@harsh1998.dev
+ */
+
+//! Cross-origin resource sharing, so web apps served from other origins
+//! can fetch files, listings and the JSON views with `fetch()`.
+
+/// Response headers a page may read besides the CORS-safelisted ones;
+/// download managers in the browser need these to resume and verify.
+const EXPOSED_HEADERS: &str =
+    "Content-Length, Content-Range, Content-Disposition, Accept-Ranges, ETag, Last-Modified";
+
+pub struct Cors {
+    /// Allowed origins such as `https://app.example.com`, or `*` for any.
+    origins: Vec<String>,
+    methods: String,
+    headers: String,
+    max_age: u64,
+}
+
+impl Cors {
+    pub fn new(origins: &[String], methods: &[String], headers: &[String], max_age: u64) -> Self {
+        let join = |items: &[String]| {
+            items
+                .iter()
+                .map(|item| item.trim())
+                .filter(|item| !item.is_empty())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        Cors {
+            origins: origins
+                .iter()
+                .map(|origin| origin.trim().trim_end_matches('/').to_string())
+                .filter(|origin| !origin.is_empty())
+                .collect(),
+            methods: join(methods),
+            headers: join(headers),
+            max_age,
+        }
+    }
+
+    /// The headers every response to a request from `origin` carries. An
+    /// origin that is not allowed gets no Access-Control-Allow-Origin, so
+    /// the browser keeps the response from the page.
+    pub fn response_headers(&self, origin: Option<&str>) -> String {
+        let allow_origin = if self.origins.iter().any(|allowed| allowed == "*") {
+            "*"
+        } else {
+            match origin {
+                Some(origin) if self.origins.iter().any(|allowed| allowed == origin) => origin,
+                // The answer depends on who asked, which caches must know.
+                _ => return String::from("Vary: Origin\r\n"),
+            }
+        };
+        let mut headers = format!(
+            "Access-Control-Allow-Origin: {}\r\nAccess-Control-Expose-Headers: {}\r\n",
+            allow_origin, EXPOSED_HEADERS
+        );
+        if allow_origin != "*" {
+            headers.push_str("Vary: Origin\r\n");
+        }
+        headers
+    }
+
+    /// The headers a preflight (an OPTIONS request carrying
+    /// Access-Control-Request-Method) gets on top of `response_headers`.
+    pub fn preflight_headers(&self) -> String {
+        let mut headers = format!(
+            "Access-Control-Allow-Methods: {}\r\nAccess-Control-Max-Age: {}\r\n",
+            self.methods, self.max_age
+        );
+        if !self.headers.is_empty() {
+            headers.push_str(&format!(
+                "Access-Control-Allow-Headers: {}\r\n",
+                self.headers
+            ));
+        }
+        headers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    #[test]
+    fn only_listed_origins_are_allowed() {
+        let origins = strings(&["https://app.example.com/", " ", "http://localhost:3000"]);
+        let cors = Cors::new(&origins, &strings(&["GET"]), &[], 600);
+
+        let headers = cors.response_headers(Some("https://app.example.com"));
+        assert!(headers.contains("Access-Control-Allow-Origin: https://app.example.com\r\n"));
+        assert!(headers.contains("Access-Control-Expose-Headers: "));
+        assert!(headers.contains("Vary: Origin\r\n"));
+
+        // Nothing for the browser to act on, but caches still learn the
+        // answer depends on the origin.
+        for origin in [Some("https://evil.example"), Some("null"), None] {
+            assert_eq!(cors.response_headers(origin), "Vary: Origin\r\n");
+        }
+    }
+
+    #[test]
+    fn wildcard_allows_any_origin() {
+        let cors = Cors::new(&strings(&["*"]), &strings(&["GET"]), &[], 600);
+        for origin in [Some("https://evil.example"), None] {
+            let headers = cors.response_headers(origin);
+            assert!(headers.starts_with("Access-Control-Allow-Origin: *\r\n"));
+            assert!(!headers.contains("Vary"));
+        }
+    }
+
+    #[test]
+    fn preflight_lists_methods_and_headers() {
+        let methods = strings(&["GET", " HEAD ", ""]);
+        let cors = Cors::new(&strings(&["*"]), &methods, &[], 600);
+        assert_eq!(
+            cors.preflight_headers(),
+            "Access-Control-Allow-Methods: GET, HEAD\r\nAccess-Control-Max-Age: 600\r\n"
+        );
+        let cors = Cors::new(&[], &methods, &strings(&["Range", "If-Range"]), 0);
+        assert!(cors
+            .preflight_headers()
+            .ends_with("Access-Control-Allow-Headers: Range, If-Range\r\n"));
+    }
+}
@@ -0,0 +1,173 @@
+/*
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ * More licensing information can be found in the project LICENSE file
+ * Author: Harshit Jain
+ * Email: reach@harsh1998.dev
+ */
+
+//! Read-only WebDAV (RFC 4918): PROPFIND answers, so the served tree can
+//! be mounted for browsing by Windows Explorer, macOS Finder or davfs2.
+//! Files are still fetched with plain GET.
+
+use crate::access::Access;
+use crate::ignore_rules::IgnoreRules;
+use std::fs;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+/// The methods every resource answers to with `--webdav`.
+pub const ALLOWED_METHODS: &str = "GET, HEAD, OPTIONS, PROPFIND";
+
+/// Headers OPTIONS carries to announce WebDAV. Class 1 is the read and
+/// write core without locking; Explorer also looks for MS-Author-Via.
+pub const OPTIONS_HEADERS: &str = "DAV: 1\r\nMS-Author-Via: DAV\r\n";
+
+/// The body of the 403 refusing `Depth: infinity`, which would walk the
+/// whole tree in one response (RFC 4918 section 9.1).
+pub const FINITE_DEPTH_ERROR: &str = "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+     <D:error xmlns:D=\"DAV:\"><D:propfind-finite-depth/></D:error>\n";
+
+/// Reads the Depth header of a PROPFIND. A missing header means infinity,
+/// which like an explicit one yields `None`.
+pub fn parse_depth(header: Option<&str>) -> Option<usize> {
+    match header.map(str::trim) {
+        Some("0") => Some(0),
+        Some("1") => Some(1),
+        _ => None,
+    }
+}
+
+/// Renders the 207 Multi-Status body for `path` and, at depth 1, the
+/// entries a listing of it would show under its `access` rules. Every
+/// resource reports the same properties whatever the request body asked
+/// for, which is all that clients mounting a share need.
+pub fn multistatus(
+    root: &Path,
+    path: &Path,
+    access: &Access,
+    depth: usize,
+    ignore_rules: &IgnoreRules,
+) -> String {
+    let mut body = String::from(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n",
+    );
+    body += &response(root, path);
+    if depth > 0 && path.is_dir() && access.can_list(access.realm()) {
+        let mut entries: Vec<_> = fs::read_dir(path)
+            .map(|entries| entries.flatten().map(|entry| entry.path()).collect())
+            .unwrap_or_default();
+        entries.retain(|entry| !ignore_rules.is_unlisted(entry));
+        entries.sort();
+        for entry in entries {
+            body += &response(root, &entry);
+        }
+    }
+    body + "</D:multistatus>\n"
+}
+
+/// One `<D:response>` element, or nothing for an entry that vanished.
+fn response(root: &Path, path: &Path) -> String {
+    let Ok(metadata) = fs::metadata(path) else {
+        return String::new();
+    };
+    let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+    let mut href: String = path
+        .strip_prefix(root)
+        .unwrap_or(path)
+        .iter()
+        .map(|segment| format!("/{}", crate::encode_query_value(&segment.to_string_lossy())))
+        .collect();
+    let name = path
+        .file_name()
+        .filter(|_| path != root)
+        .unwrap_or_default()
+        .to_string_lossy();
+    let properties = if metadata.is_dir() {
+        href.push('/');
+        String::from("<D:resourcetype><D:collection/></D:resourcetype>")
+    } else {
+        format!(
+            "<D:resourcetype/><D:getcontentlength>{}</D:getcontentlength>\
+             <D:getcontenttype>{}</D:getcontenttype><D:getetag>{}</D:getetag>",
+            metadata.len(),
+            crate::content_type(path),
+            crate::escape_html(&crate::file_etag(metadata.len(), modified))
+        )
+    };
+    format!(
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop>\
+         <D:displayname>{}</D:displayname>{}\
+         <D:getlastmodified>{}</D:getlastmodified>\
+         </D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n",
+        crate::escape_html(&href),
+        crate::escape_html(&name),
+        properties,
+        crate::http_date(
+            modified
+                .duration_since(UNIX_EPOCH)
+                .map(|since| since.as_secs())
+                .unwrap_or_default()
+        )
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::access::AccessRules;
+    use crate::ignore_rules::HiddenFiles;
+
+    #[test]
+    fn depth_is_zero_one_or_infinite() {
+        assert_eq!(parse_depth(Some("0")), Some(0));
+        assert_eq!(parse_depth(Some(" 1 ")), Some(1));
+        assert_eq!(parse_depth(Some("infinity")), None);
+        assert_eq!(parse_depth(Some("2")), None);
+        assert_eq!(parse_depth(None), None);
+    }
+
+    #[test]
+    fn multistatus_lists_visible_entries() {
+        let scratch = tempfile::tempdir().unwrap();
+        let root = scratch.path();
+        fs::create_dir(root.join("sub dir")).unwrap();
+        fs::write(root.join("a&b.txt"), "hello").unwrap();
+        fs::write(root.join(".hidden"), "").unwrap();
+        let access = AccessRules::new(root.to_path_buf()).resolve(root);
+        let ignore_rules = IgnoreRules::new(root.to_path_buf(), false, HiddenFiles::Unlisted);
+
+        let shallow = multistatus(root, root, &access, 0, &ignore_rules);
+        assert_eq!(shallow.matches("<D:response>").count(), 1);
+        assert!(shallow.contains("<D:href>/</D:href>"));
+
+        let body = multistatus(root, root, &access, 1, &ignore_rules);
+        assert!(body.starts_with("<?xml"));
+        assert!(body.ends_with("</D:multistatus>\n"));
+        assert_eq!(body.matches("<D:response>").count(), 3);
+        assert!(body.contains(
+            "<D:href>/a%26b.txt</D:href><D:propstat><D:prop>\
+             <D:displayname>a&amp;b.txt</D:displayname><D:resourcetype/>\
+             <D:getcontentlength>5</D:getcontentlength>\
+             <D:getcontenttype>text/plain; charset=utf-8</D:getcontenttype>"
+        ));
+        assert!(body.contains(
+            "<D:href>/sub%20dir/</D:href><D:propstat><D:prop>\
+             <D:displayname>sub dir</D:displayname>\
+             <D:resourcetype><D:collection/></D:resourcetype>"
+        ));
+        assert!(!body.contains(".hidden"));
+    }
+
+    #[test]
+    fn multistatus_respects_listing_off() {
+        let scratch = tempfile::tempdir().unwrap();
+        let root = scratch.path();
+        fs::write(root.join(".hdl_access"), "listing = off\n").unwrap();
+        fs::write(root.join("a.txt"), "").unwrap();
+        let access = AccessRules::new(root.to_path_buf()).resolve(root);
+        let ignore_rules = IgnoreRules::new(root.to_path_buf(), false, HiddenFiles::Show);
+
+        let body = multistatus(root, root, &access, 1, &ignore_rules);
+        assert_eq!(body.matches("<D:response>").count(), 1);
+    }
+}
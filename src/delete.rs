@@ -0,0 +1,116 @@
+/*
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ * More licensing information can be found in the project LICENSE file
+ * Author: Harshit Jain
+ * Email: reach@harsh1998.dev
+ */
+
+//! `DELETE` with `--enable-delete`, for managing a drop-box directory
+//! remotely. Only subtrees whose `.hdl_access` file has accounts take
+//! deletions, so every one is made by a logged-in user, and each is
+//! recorded in the `--audit-log` under that user.
+
+use crate::response::Response;
+use crate::{resolve_request_path, send_no_content, ServerConfig, Target};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::time::Duration;
+
+/// Deletes a file, or an empty directory, the request names.
+pub fn route<'a>(target: &Target, config: &ServerConfig) -> Response<'a> {
+    if !target.request.path().starts_with('/') {
+        return Response::Error(400, "Bad Request", "Invalid request path");
+    }
+    let path = resolve_request_path(target.root, target.path, config.normalization);
+    if target.ignore_rules.is_ignored(&path) || fs::symlink_metadata(&path).is_err() {
+        return Response::Error(404, "Not Found", "File or directory not found");
+    }
+    let access = target.access_rules.resolve_path(&path);
+    if access.denied {
+        return Response::Error(403, "Forbidden", "Access to this directory is restricted");
+    }
+    if access.auth.is_none() {
+        return Response::Error(
+            403,
+            "Forbidden",
+            "Only directories with .hdl_access accounts take deletions",
+        );
+    }
+    if let Err(response) = target.authorize(&access, config) {
+        return response;
+    }
+    if !is_inside(target.root, &path) {
+        return Response::Error(403, "Forbidden", "Access denied");
+    }
+
+    // A symlink is removed itself, never what it points to.
+    let result = match fs::symlink_metadata(&path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir(&path),
+        Ok(_) if !access.can_download(&path, target.extensions) => {
+            return Response::Error(403, "Forbidden", "Only allowed files can be deleted");
+        }
+        Ok(_) => fs::remove_file(&path),
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(()) => {
+            println!("Deleted {:?} for {}", path, target.peer);
+            Response::Stream(Box::new(|stream| {
+                if let Some(audit) = &stream.audit {
+                    audit.record("", 0, None, Duration::ZERO, "deleted");
+                }
+                send_no_content(stream, "");
+            }))
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            Response::Error(404, "Not Found", "File or directory not found")
+        }
+        Err(e) if e.kind() == ErrorKind::DirectoryNotEmpty => {
+            Response::Error(409, "Conflict", "Directory is not empty")
+        }
+        Err(e) => {
+            eprintln!("Error deleting {:?}: {}", path, e);
+            Response::Error(403, "Forbidden", "File or directory cannot be deleted")
+        }
+    }
+}
+
+/// Whether `path` is strictly below `root` once the directories leading to
+/// it are resolved, so neither the root nor anything a symlinked
+/// directory leads to can be deleted.
+fn is_inside(root: &Path, path: &Path) -> bool {
+    path != root
+        && path
+            .parent()
+            .and_then(|parent| parent.canonicalize().ok())
+            .is_some_and(|parent| parent.starts_with(root))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_paths_below_root_are_inside() {
+        let scratch = tempfile::tempdir().unwrap();
+        let base = scratch.path().canonicalize().unwrap();
+        let root = base.join("root");
+        fs::create_dir_all(root.join("drop")).unwrap();
+        fs::create_dir(base.join("outside")).unwrap();
+
+        assert!(is_inside(&root, &root.join("drop")));
+        assert!(is_inside(&root, &root.join("drop/a.txt")));
+        assert!(!is_inside(&root, &root));
+        assert!(!is_inside(&root, &base.join("outside/a.txt")));
+        // Nothing below a directory that does not exist can be deleted.
+        assert!(!is_inside(&root, &root.join("missing/a.txt")));
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(base.join("outside"), root.join("link")).unwrap();
+            // The link itself may go, but not what is behind it.
+            assert!(is_inside(&root, &root.join("link")));
+            assert!(!is_inside(&root, &root.join("link/a.txt")));
+        }
+    }
+}
@@ -0,0 +1,123 @@
+/*
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ * More licensing information can be found in the project LICENSE file
+ * Author: Harshit Jain
+ * Email: reach@harsh1998.dev
+ */
+
+//! A bounded cache for data worked out by reading a whole file, such as
+//! `/_parts` manifests and zsync control files. An entry is reused until
+//! the file's size or modification time changes, the least recently used
+//! entries make room for new ones, and concurrent requests for the same
+//! key wait for one generation instead of each reading the file.
+
+use std::collections::HashMap;
+use std::fs::{self, Metadata};
+use std::hash::Hash;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+pub struct FileCache<K, V> {
+    capacity: usize,
+    state: Mutex<State<K, V>>,
+}
+
+struct State<K, V> {
+    entries: HashMap<K, Entry<V>>,
+    /// One lock per key being generated, held for the whole generation.
+    generating: HashMap<K, Arc<Mutex<()>>>,
+    /// Bumped on every hit, to tell which entry was used last.
+    clock: u64,
+}
+
+struct Entry<V> {
+    length: u64,
+    modified: SystemTime,
+    last_used: u64,
+    value: Arc<V>,
+}
+
+impl<K: Clone + Eq + Hash, V> FileCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        FileCache {
+            capacity,
+            state: Mutex::new(State {
+                entries: HashMap::new(),
+                generating: HashMap::new(),
+                clock: 0,
+            }),
+        }
+    }
+
+    /// Returns the cached value for `key` if `path` has not changed since it
+    /// was made, and otherwise calls `generate` with the file's metadata.
+    pub fn get_or_generate(
+        &self,
+        key: K,
+        path: &Path,
+        generate: impl FnOnce(&Metadata) -> io::Result<V>,
+    ) -> io::Result<Arc<V>> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata.modified()?;
+        if let Some(value) = self.lookup(&key, metadata.len(), modified) {
+            return Ok(value);
+        }
+
+        let generation = Arc::clone(
+            self.state
+                .lock()
+                .unwrap()
+                .generating
+                .entry(key.clone())
+                .or_default(),
+        );
+        let _generating = generation.lock().unwrap();
+        // Whoever held the lock before may have just made it.
+        if let Some(value) = self.lookup(&key, metadata.len(), modified) {
+            return Ok(value);
+        }
+
+        // Reading a large file takes a while, so it is done without holding
+        // the cache lock.
+        let generated = generate(&metadata).map(Arc::new);
+        let mut state = self.state.lock().unwrap();
+        state.generating.remove(&key);
+        let value = generated?;
+        if state.entries.len() >= self.capacity && !state.entries.contains_key(&key) {
+            let oldest = state
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                state.entries.remove(&oldest);
+            }
+        }
+        state.clock += 1;
+        let last_used = state.clock;
+        state.entries.insert(
+            key,
+            Entry {
+                length: metadata.len(),
+                modified,
+                last_used,
+                value: Arc::clone(&value),
+            },
+        );
+        Ok(value)
+    }
+
+    fn lookup(&self, key: &K, length: u64, modified: SystemTime) -> Option<Arc<V>> {
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        let clock = state.clock;
+        let entry = state.entries.get_mut(key)?;
+        if entry.length != length || entry.modified != modified {
+            return None;
+        }
+        entry.last_used = clock;
+        Some(Arc::clone(&entry.value))
+    }
+}
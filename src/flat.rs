@@ -0,0 +1,146 @@
+/*
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ * More licensing information can be found in the project LICENSE file
+ * Author: Harshit Jain
+ * Email: reach@harsh1998.dev
+ */
+
+//! `/_flat`: every file in the tree with its full relative path, for
+//! finding something without clicking through directories.
+
+use crate::access::{Access, AccessRules};
+use crate::ignore_rules::IgnoreRules;
+use crate::response::Response;
+use crate::{
+    encode_path, escape_html, format_last_modified, modified_secs, static_url, ServerConfig, Target,
+};
+use humansize::{file_size_opts as options, FileSize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// `/_flat`: every file in the tree on one page, filtered and sorted in
+/// the browser.
+pub fn route<'a>(target: &Target, config: &ServerConfig) -> Response<'a> {
+    let access = target.access_rules.resolve(target.root);
+    if let Err(response) = target.authorize(&access, config) {
+        return response;
+    }
+    let html = generate_flat_listing(
+        target.root,
+        &access,
+        target.ignore_rules,
+        target.access_rules,
+    );
+    Response::Page {
+        content_type: "text/html; charset=utf-8",
+        headers: config.page_policy.to_string(),
+        body: html.into_bytes(),
+    }
+}
+
+fn generate_flat_listing(
+    root: &Path,
+    access: &Access,
+    ignore_rules: &IgnoreRules,
+    access_rules: &AccessRules,
+) -> String {
+    let mut files = Vec::new();
+    collect_files(
+        root,
+        access,
+        &mut files,
+        ignore_rules,
+        access_rules,
+        access.realm(),
+    );
+    files.sort();
+
+    let rows: String = files
+        .iter()
+        .filter_map(|path| {
+            let metadata = fs::metadata(path).ok().filter(|metadata| metadata.is_file())?;
+            let relative_path = path.strip_prefix(root).ok()?;
+            Some(format!(
+                "<tr><td><a href=\"/{}\">{}</a></td><td data-value=\"{}\">{}</td><td data-value=\"{}\">{}</td></tr>",
+                encode_path(relative_path),
+                escape_html(&relative_path.to_string_lossy()),
+                metadata.len(),
+                metadata.len().file_size(options::BINARY).unwrap(),
+                modified_secs(&metadata),
+                format_last_modified(&metadata)
+            ))
+        })
+        .collect();
+
+    format!(
+        r#"
+        <!DOCTYPE html>
+        <html lang="en">
+        <head>
+            <meta charset="UTF-8">
+            <meta name="viewport" content="width=device-width, initial-scale=1.0">
+            <title>All files in /</title>
+            <link rel="stylesheet" href="{style}">
+        </head>
+        <body>
+            <div class="container">
+                <h1 title="/">All Files</h1>
+                <input id="filter" class="filter" type="search" placeholder="Filter by path" autofocus>
+                <table id="files">
+                    <thead>
+                        <tr>
+                            <th>Path</th>
+                            <th>Size</th>
+                            <th>Last Modified</th>
+                        </tr>
+                    </thead>
+                    <tbody>
+                        {rows}
+                    </tbody>
+                </table>
+            </div>
+            <script src="{script}"></script>
+        </body>
+        </html>
+        "#,
+        style = static_url("style.css"),
+        script = static_url("flat.js"),
+    )
+}
+
+/// Recursively gathers every entry below `directory` that is not a real
+/// directory. Symlinked directories are not followed, so links pointing back
+/// up the tree cannot loop.
+/// Directories whose listing is switched off are skipped entirely; their
+/// files stay downloadable to anyone who already knows the name. So are
+/// directories guarded by accounts other than those of `realm`. `access`
+/// holds the rules for `directory`; each subdirectory only adds its own.
+fn collect_files(
+    directory: &Path,
+    access: &Access,
+    files: &mut Vec<PathBuf>,
+    ignore_rules: &IgnoreRules,
+    access_rules: &AccessRules,
+    realm: Option<&str>,
+) {
+    if !access.can_list(realm) {
+        return;
+    }
+    let Ok(entries) = fs::read_dir(directory) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        if ignore_rules.is_unlisted(&entry.path()) {
+            continue;
+        }
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => {
+                let path = entry.path();
+                let access = access_rules.descend(access, &path);
+                collect_files(&path, &access, files, ignore_rules, access_rules, realm)
+            }
+            Ok(_) => files.push(entry.path()),
+            Err(_) => {}
+        }
+    }
+}
@@ -0,0 +1,606 @@
+/*
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ * More licensing information can be found in the project LICENSE file
+ * Author: Harshit Jain
+ * Email: reach@harsh1998.dev
+ */
+
+//! Read-only, passive-mode FTP listener (RFC 959, EPSV from RFC 2428)
+//! serving the same directory as the HTTP side. Sessions go through the
+//! same connection ceiling, bans, rate limit and country rules as HTTP
+//! connections. Anonymous logins see everything an access file does not
+//! guard with accounts; a named login also reaches the subtrees whose
+//! `.hdl_access` accounts it belongs to, its password checked (and
+//! throttled) as HTTP Basic credentials are.
+
+use crate::access::{Access, AccessRules, Extensions};
+use crate::admission::{Admission, Login, Refusal};
+use crate::ignore_rules::IgnoreRules;
+use chrono::{DateTime, Local, Utc};
+use std::fs::{self, File};
+use std::io::{self, prelude::*, BufReader};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CONTROL_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+const DATA_ACCEPT_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_COMMAND_LENGTH: u64 = 4096;
+
+struct Session {
+    control: TcpStream,
+    peer: SocketAddr,
+    root: PathBuf,
+    extensions: Arc<Extensions>,
+    ignore_rules: Arc<IgnoreRules>,
+    access_rules: Arc<AccessRules>,
+    write_timeout: Duration,
+    cwd: PathBuf,
+    passive: Option<TcpListener>,
+    restart_offset: u64,
+    admission: Arc<Admission>,
+    /// The name given with USER, until PASS completes the login.
+    user: Option<String>,
+    /// The user and password of a named login, checked against the
+    /// accounts of every protected subtree the session touches.
+    credentials: Option<(String, String)>,
+}
+
+/// Why a path is not available to a session.
+enum Refused {
+    /// Missing, hidden, outside the root or under an access file that
+    /// could not be honoured.
+    NotFound,
+    /// Guarded by accounts the session is not logged in as.
+    NeedsLogin,
+    /// Too many failed logins from the client's address of late.
+    Throttled,
+}
+
+pub fn serve(
+    bind_address: String,
+    root: PathBuf,
+    extensions: Arc<Extensions>,
+    ignore_rules: Arc<IgnoreRules>,
+    access_rules: Arc<AccessRules>,
+    admission: Arc<Admission>,
+    write_timeout: Duration,
+) {
+    let listener = match TcpListener::bind(&bind_address) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Error binding FTP listener on {}: {}", bind_address, e);
+            return;
+        }
+    };
+    println!("FTP listening on {}", bind_address);
+
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Error accepting FTP connection: {}", e);
+                continue;
+            }
+        };
+        let address = stream.peer_addr().ok().map(|address| address.ip());
+        let admitted = match address {
+            Some(address) if !admission.is_country_allowed(address) => {
+                Err("Access from your location is not permitted")
+            }
+            _ => admission.admit(address).map_err(|refusal| match refusal {
+                Refusal::Overloaded => "Too many connections, try again later",
+                Refusal::Banned => "Access denied",
+                Refusal::RateLimited(_) => "Too many requests, try again later",
+            }),
+        };
+        let connection = match admitted {
+            Ok(connection) => connection,
+            Err(message) => {
+                // Like the HTTP refusals, written without waiting on a
+                // client that does not read.
+                let _ = stream.set_nonblocking(true);
+                let _ = stream.write_all(format!("421 {}\r\n", message).as_bytes());
+                continue;
+            }
+        };
+        let root = root.clone();
+        let extensions = Arc::clone(&extensions);
+        let ignore_rules = Arc::clone(&ignore_rules);
+        let access_rules = Arc::clone(&access_rules);
+        let admission = Arc::clone(&admission);
+        thread::spawn(move || {
+            let _connection = connection;
+            if let Err(e) = handle_session(
+                stream,
+                root,
+                extensions,
+                ignore_rules,
+                access_rules,
+                admission,
+                write_timeout,
+            ) {
+                eprintln!("FTP session ended with error: {}", e);
+            }
+        });
+    }
+}
+
+fn handle_session(
+    stream: TcpStream,
+    root: PathBuf,
+    extensions: Arc<Extensions>,
+    ignore_rules: Arc<IgnoreRules>,
+    access_rules: Arc<AccessRules>,
+    admission: Arc<Admission>,
+    write_timeout: Duration,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(CONTROL_IDLE_TIMEOUT))?;
+    stream.set_write_timeout(Some(write_timeout))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut session = Session {
+        peer: stream.peer_addr()?,
+        control: stream,
+        cwd: root.clone(),
+        root,
+        extensions,
+        ignore_rules,
+        access_rules,
+        write_timeout,
+        passive: None,
+        restart_offset: 0,
+        admission,
+        user: None,
+        credentials: None,
+    };
+    session.reply(220, "hdl_sv read-only FTP ready")?;
+
+    loop {
+        let mut line = String::new();
+        if (&mut reader)
+            .take(MAX_COMMAND_LENGTH)
+            .read_line(&mut line)?
+            == 0
+        {
+            return Ok(());
+        }
+        let line = line.trim_end_matches(['\r', '\n']);
+        let (command, argument) = match line.split_once(' ') {
+            Some((command, argument)) => (command.to_ascii_uppercase(), argument),
+            None => (line.to_ascii_uppercase(), ""),
+        };
+
+        match command.as_str() {
+            "USER" => {
+                session.credentials = None;
+                if argument.eq_ignore_ascii_case("anonymous") || argument.eq_ignore_ascii_case("ftp")
+                {
+                    session.user = None;
+                    session.reply(331, "Anonymous login, any password will do")?
+                } else {
+                    session.user = Some(argument.to_string());
+                    session.reply(331, "Password required")?
+                }
+            }
+            // The password is checked by each protected subtree the session
+            // enters, since every access file has accounts of its own.
+            "PASS" => match session.user.take() {
+                Some(user) => {
+                    session.credentials = Some((user, argument.to_string()));
+                    session.reply(230, "Logged in, read-only access")?
+                }
+                None => session.reply(230, "Logged in anonymously, read-only access")?,
+            },
+            "SYST" => session.reply(215, "UNIX Type: L8")?,
+            "FEAT" => session.control.write_all(
+                b"211-Features:\r\n EPSV\r\n MDTM\r\n PASV\r\n REST STREAM\r\n SIZE\r\n UTF8\r\n211 End\r\n",
+            )?,
+            "OPTS" if argument.eq_ignore_ascii_case("UTF8 ON") => {
+                session.reply(200, "UTF8 mode enabled")?
+            }
+            "NOOP" => session.reply(200, "OK")?,
+            "TYPE" => session.reply(200, "Type set, transfers are always binary")?,
+            "MODE" if argument.eq_ignore_ascii_case("S") => session.reply(200, "Mode set to S")?,
+            "STRU" if argument.eq_ignore_ascii_case("F") => session.reply(200, "Structure set to F")?,
+            "PWD" | "XPWD" => {
+                let message = format!("\"{}\" is the current directory", session.virtual_cwd());
+                session.reply(257, &message)?
+            }
+            "CWD" | "XCWD" => session.change_directory(argument)?,
+            "CDUP" | "XCUP" => session.change_directory("..")?,
+            "PASV" => session.enter_passive(false)?,
+            "EPSV" => session.enter_passive(true)?,
+            "PORT" | "EPRT" => session.reply(502, "Active mode is not supported, use PASV")?,
+            "LIST" | "NLST" => session.list(argument, command == "NLST")?,
+            "RETR" => session.retrieve(argument)?,
+            "REST" => match argument.parse() {
+                Ok(offset) => {
+                    session.restart_offset = offset;
+                    session.reply(350, "Restart position accepted")?
+                }
+                Err(_) => session.reply(501, "Invalid restart position")?,
+            },
+            "SIZE" => match session.resolve(argument) {
+                Ok((path, _)) if path.is_file() => {
+                    let size = fs::metadata(&path)?.len().to_string();
+                    session.reply(213, &size)?
+                }
+                Ok(_) => session.reply(550, "File not found")?,
+                Err(refused) => session.refuse(refused, "File not found")?,
+            },
+            "MDTM" => match session.resolve(argument) {
+                Ok((path, _)) if path.is_file() => {
+                    let modified: DateTime<Utc> = fs::metadata(&path)?.modified()?.into();
+                    session.reply(213, &modified.format("%Y%m%d%H%M%S").to_string())?
+                }
+                Ok(_) => session.reply(550, "File not found")?,
+                Err(refused) => session.refuse(refused, "File not found")?,
+            },
+            "STOR" | "STOU" | "APPE" | "DELE" | "MKD" | "XMKD" | "RMD" | "XRMD" | "RNFR"
+            | "RNTO" | "SITE" => session.reply(550, "Server is read-only")?,
+            "QUIT" => {
+                session.reply(221, "Goodbye")?;
+                return Ok(());
+            }
+            _ => session.reply(502, "Command not implemented")?,
+        }
+    }
+}
+
+impl Session {
+    fn reply(&mut self, code: u16, message: &str) -> io::Result<()> {
+        self.control
+            .write_all(format!("{} {}\r\n", code, message).as_bytes())
+    }
+
+    /// Answers a command whose path `resolve` refused; `missing` is the
+    /// message for a path that is not there.
+    fn refuse(&mut self, refused: Refused, missing: &str) -> io::Result<()> {
+        match refused {
+            Refused::NotFound => self.reply(550, missing),
+            Refused::NeedsLogin => self.reply(530, "Log in with an account for this directory"),
+            Refused::Throttled => self.reply(530, "Too many failed logins, try again later"),
+        }
+    }
+
+    fn virtual_cwd(&self) -> String {
+        let relative = self.cwd.strip_prefix(&self.root).unwrap_or(Path::new(""));
+        format!("/{}", relative.to_string_lossy())
+    }
+
+    /// Maps a client-supplied path onto the served tree, refusing anything
+    /// that escapes the root once symlinks and `..` are resolved, that
+    /// ignore rules hide or that an access file puts off limits. Subtrees
+    /// needing credentials are only open to a login with one of their
+    /// accounts.
+    fn resolve(&self, argument: &str) -> Result<(PathBuf, Access), Refused> {
+        let joined = if argument.starts_with('/') {
+            self.root.join(argument.trim_start_matches('/'))
+        } else {
+            self.cwd.join(argument)
+        };
+        if self.ignore_rules.is_ignored(&joined) {
+            return Err(Refused::NotFound);
+        }
+        let path = joined
+            .canonicalize()
+            .ok()
+            .filter(|path| path.starts_with(&self.root) && !self.ignore_rules.is_ignored(path))
+            .ok_or(Refused::NotFound)?;
+        let access = self.access_rules.resolve_path(&path);
+        if access.denied {
+            return Err(Refused::NotFound);
+        }
+        if let Some(auth) = &access.auth {
+            let credentials = self
+                .credentials
+                .as_ref()
+                .map(|(user, password)| (user.as_str(), password.as_str()));
+            match self.admission.login(auth, Some(self.peer.ip()), credentials) {
+                Login::Accepted(_) => {}
+                Login::Rejected => return Err(Refused::NeedsLogin),
+                Login::Throttled(_) => return Err(Refused::Throttled),
+            }
+        }
+        Ok((path, access))
+    }
+
+    fn change_directory(&mut self, argument: &str) -> io::Result<()> {
+        match self.resolve(argument) {
+            Ok((path, _)) if path.is_dir() => {
+                self.cwd = path;
+                self.reply(250, "Directory changed")
+            }
+            Ok(_) => self.reply(550, "Directory not found"),
+            Err(refused) => self.refuse(refused, "Directory not found"),
+        }
+    }
+
+    fn enter_passive(&mut self, extended: bool) -> io::Result<()> {
+        let local_ip = self.control.local_addr()?.ip();
+        let listener = TcpListener::bind((local_ip, 0))?;
+        let port = listener.local_addr()?.port();
+        self.passive = Some(listener);
+
+        if extended {
+            self.reply(
+                229,
+                &format!("Entering Extended Passive Mode (|||{}|)", port),
+            )
+        } else if let IpAddr::V4(ip) = local_ip {
+            let [a, b, c, d] = ip.octets();
+            let message = format!(
+                "Entering Passive Mode ({},{},{},{},{},{})",
+                a,
+                b,
+                c,
+                d,
+                port >> 8,
+                port & 0xff
+            );
+            self.reply(227, &message)
+        } else {
+            self.passive = None;
+            self.reply(522, "PASV needs IPv4, use EPSV")
+        }
+    }
+
+    /// Waits for the client to open the data connection announced by the
+    /// last PASV/EPSV. Connections from other hosts are dropped so the
+    /// port cannot be hijacked.
+    fn accept_data_connection(&mut self) -> Option<TcpStream> {
+        let listener = self.passive.take()?;
+        listener.set_nonblocking(true).ok()?;
+        let deadline = Instant::now() + DATA_ACCEPT_TIMEOUT;
+        while Instant::now() < deadline {
+            match listener.accept() {
+                Ok((stream, address)) if address.ip() == self.peer.ip() => {
+                    // A client that stops reading or writing mid-transfer
+                    // must not hold the session forever.
+                    stream.set_nonblocking(false).ok()?;
+                    stream.set_read_timeout(Some(self.write_timeout)).ok()?;
+                    stream.set_write_timeout(Some(self.write_timeout)).ok()?;
+                    return Some(stream);
+                }
+                Ok(_) => continue,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(50));
+                }
+                Err(_) => return None,
+            }
+        }
+        None
+    }
+
+    fn list(&mut self, argument: &str, names_only: bool) -> io::Result<()> {
+        // Clients commonly pass ls-style flags such as `-la`; they are ignored.
+        let argument = argument
+            .split_whitespace()
+            .filter(|part| !part.starts_with('-'))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let (target, access) = match self.resolve(&argument) {
+            Ok(resolved) => resolved,
+            Err(refused) => return self.refuse(refused, "Directory not found"),
+        };
+        if target.is_dir() && !access.can_list(access.realm()) {
+            return self.reply(550, "Directory listing is disabled");
+        }
+        let mut entries: Vec<PathBuf> = if target.is_dir() {
+            fs::read_dir(&target)?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| !self.ignore_rules.is_unlisted(path))
+                .collect()
+        } else {
+            vec![target]
+        };
+        entries.sort();
+
+        let listing: String = entries
+            .iter()
+            .filter_map(|path| {
+                let name = path.file_name()?.to_string_lossy();
+                if names_only {
+                    return Some(format!("{}\r\n", name));
+                }
+                let metadata = fs::metadata(path).ok()?;
+                let modified: DateTime<Local> = metadata.modified().ok()?.into();
+                Some(format!(
+                    "{} 1 ftp ftp {:>13} {} {}\r\n",
+                    if metadata.is_dir() {
+                        "dr-xr-xr-x"
+                    } else {
+                        "-r--r--r--"
+                    },
+                    metadata.len(),
+                    modified.format("%b %e %H:%M"),
+                    name
+                ))
+            })
+            .collect();
+
+        self.send_data("directory listing", |data| {
+            data.write_all(listing.as_bytes())
+        })
+    }
+
+    fn retrieve(&mut self, argument: &str) -> io::Result<()> {
+        let offset = std::mem::take(&mut self.restart_offset);
+        let (path, access) = match self.resolve(argument) {
+            Ok((path, access)) if path.is_file() => (path, access),
+            Ok(_) => return self.reply(550, "File not found"),
+            Err(refused) => return self.refuse(refused, "File not found"),
+        };
+        if !access.can_download(&path, &self.extensions) {
+            return self.reply(550, "Only allowed files can be downloaded");
+        }
+        let mut file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if crate::is_sharing_violation(&e) => {
+                return self.reply(450, "File is in use by another process");
+            }
+            Err(_) => return self.reply(550, "File not found"),
+        };
+        file.seek(io::SeekFrom::Start(offset))?;
+
+        self.send_data("BINARY mode data connection", |data| {
+            io::copy(&mut file, data).map(|_| ())
+        })
+    }
+
+    fn send_data(
+        &mut self,
+        description: &str,
+        transfer: impl FnOnce(&mut TcpStream) -> io::Result<()>,
+    ) -> io::Result<()> {
+        if self.passive.is_none() {
+            return self.reply(425, "Use PASV or EPSV first");
+        }
+        self.reply(150, &format!("Opening {}", description))?;
+        let mut data = match self.accept_data_connection() {
+            Some(data) => data,
+            None => return self.reply(425, "Can't open data connection"),
+        };
+        match transfer(&mut data) {
+            Ok(()) => {
+                drop(data);
+                self.reply(226, "Transfer complete")
+            }
+            Err(_) => self.reply(426, "Connection closed; transfer aborted"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ignore_rules::HiddenFiles;
+    use crate::rate_limit::RateLimiter;
+
+    /// A session over a loopback control connection, in a served root
+    /// inside a scratch directory with `outside.txt` beside it.
+    fn session(scratch: &tempfile::TempDir) -> (Session, TcpStream) {
+        let base = scratch.path().canonicalize().unwrap();
+        let root = base.join("root");
+        for directory in ["pub", "staff", "broken"] {
+            fs::create_dir_all(root.join(directory)).unwrap();
+        }
+        for file in [
+            "pub/a.txt",
+            "pub/notes.log",
+            "staff/plan.txt",
+            "broken/b.txt",
+        ] {
+            fs::write(root.join(file), "").unwrap();
+        }
+        fs::write(base.join("outside.txt"), "").unwrap();
+        fs::write(root.join(".hdlignore"), "*.log\n").unwrap();
+        let account = format!("user = alice:{}\n", bcrypt::hash("secret", 4).unwrap());
+        fs::write(root.join("staff/.hdl_access"), account).unwrap();
+        fs::write(root.join("broken/.hdl_access"), "require = mfa\n").unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (control, peer) = listener.accept().unwrap();
+        let session = Session {
+            control,
+            peer,
+            extensions: Arc::new(Extensions {
+                allowed: Vec::new(),
+                denied: Vec::new(),
+            }),
+            ignore_rules: Arc::new(IgnoreRules::new(root.clone(), false, HiddenFiles::Deny)),
+            access_rules: Arc::new(AccessRules::new(root.clone())),
+            write_timeout: Duration::from_secs(5),
+            cwd: root.join("pub"),
+            root,
+            passive: None,
+            restart_offset: 0,
+            admission: Arc::new(Admission::new(
+                None,
+                None,
+                None,
+                None,
+                RateLimiter::new(1, 2, Vec::new()),
+            )),
+            user: None,
+            credentials: None,
+        };
+        (session, client)
+    }
+
+    fn login(session: &mut Session, password: &str) {
+        session.credentials = Some((String::from("alice"), password.to_string()));
+    }
+
+    #[test]
+    fn paths_resolve_from_cwd_or_root() {
+        let scratch = tempfile::tempdir().unwrap();
+        let (session, _client) = session(&scratch);
+        let file = session.root.join("pub/a.txt");
+        for argument in ["a.txt", "/pub/a.txt", "../pub/./a.txt"] {
+            let (path, _) = session.resolve(argument).ok().unwrap();
+            assert_eq!(path, file, "{}", argument);
+        }
+        assert_eq!(session.resolve("").ok().unwrap().0, session.cwd);
+    }
+
+    #[test]
+    fn nothing_outside_root_or_hidden() {
+        let scratch = tempfile::tempdir().unwrap();
+        let (session, _client) = session(&scratch);
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(
+            scratch.path().join("outside.txt"),
+            session.root.join("pub/link.txt"),
+        )
+        .unwrap();
+        for argument in [
+            "../../outside.txt",
+            "/../outside.txt",
+            "link.txt",
+            "notes.log",
+            "/.hdlignore",
+            "/staff/.hdl_access",
+            "missing.txt",
+            // An access file that cannot be honoured closes its subtree.
+            "/broken/b.txt",
+        ] {
+            let refused = session.resolve(argument).err();
+            assert!(matches!(refused, Some(Refused::NotFound)), "{}", argument);
+        }
+    }
+
+    #[test]
+    fn protected_paths_need_an_account() {
+        let scratch = tempfile::tempdir().unwrap();
+        let (mut session, _client) = session(&scratch);
+        let refused = session.resolve("/staff/plan.txt").err();
+        assert!(matches!(refused, Some(Refused::NeedsLogin)));
+        login(&mut session, "guess");
+        let refused = session.resolve("/staff").err();
+        assert!(matches!(refused, Some(Refused::NeedsLogin)));
+        login(&mut session, "secret");
+        assert!(session.resolve("/staff/plan.txt").is_ok());
+        // Unprotected paths do not check, or count, the password.
+        login(&mut session, "guess");
+        assert!(session.resolve("/pub/a.txt").is_ok());
+    }
+
+    #[test]
+    fn failed_logins_are_throttled() {
+        let scratch = tempfile::tempdir().unwrap();
+        let (mut session, _client) = session(&scratch);
+        login(&mut session, "guess");
+        for _ in 0..2 {
+            let refused = session.resolve("/staff/plan.txt").err();
+            assert!(matches!(refused, Some(Refused::NeedsLogin)));
+        }
+        // Past the burst even the right password waits.
+        login(&mut session, "secret");
+        let refused = session.resolve("/staff/plan.txt").err();
+        assert!(matches!(refused, Some(Refused::Throttled)));
+    }
+}
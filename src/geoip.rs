@@ -0,0 +1,60 @@
+/*
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ * More licensing information can be found in the project LICENSE file
+ * Author: Harshit Jain
+ * Email: reach@harsh1998.dev
+ */
+
+//! Country lookups against a MaxMind database (GeoLite2/GeoIP2 Country or
+//! City), used to tag log lines and to admit or refuse clients by country.
+
+use maxminddb::{path, Reader};
+use std::net::IpAddr;
+use std::path::Path;
+
+pub struct GeoIp {
+    reader: Reader<Vec<u8>>,
+    allow: Vec<String>,
+    deny: Vec<String>,
+}
+
+impl GeoIp {
+    pub fn open(
+        database: &Path,
+        allow: &[String],
+        deny: &[String],
+    ) -> Result<Self, maxminddb::MaxMindDbError> {
+        let normalize = |codes: &[String]| {
+            codes
+                .iter()
+                .map(|code| code.trim().to_ascii_uppercase())
+                .filter(|code| !code.is_empty())
+                .collect()
+        };
+        Ok(GeoIp {
+            reader: Reader::open_readfile(database)?,
+            allow: normalize(allow),
+            deny: normalize(deny),
+        })
+    }
+
+    /// The ISO 3166-1 alpha-2 code of the country `address` is registered
+    /// in, or `None` for addresses the database does not cover, such as
+    /// private and loopback ranges.
+    pub fn country(&self, address: IpAddr) -> Option<String> {
+        let result = self.reader.lookup(address).ok()?;
+        let code: Option<&str> = result.decode_path(&path!["country", "iso_code"]).ok()?;
+        code.map(str::to_string)
+    }
+
+    /// A denied country is always refused. When an allow list is given,
+    /// only the listed countries get in, and addresses of unknown origin
+    /// are refused too.
+    pub fn is_allowed(&self, country: Option<&str>) -> bool {
+        match country {
+            Some(code) if self.deny.iter().any(|denied| denied == code) => false,
+            Some(code) => self.allow.is_empty() || self.allow.iter().any(|allowed| allowed == code),
+            None => self.allow.is_empty(),
+        }
+    }
+}
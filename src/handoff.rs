@@ -0,0 +1,60 @@
+/*
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ * More licensing information can be found in the project LICENSE file
+ * Author: Harshit Jain
+ * Email: reach@harsh1998.dev
+ */
+
+//! Zero-downtime upgrades. With `--reuse-port` a new server process can
+//! bind the port an old one is still accepting on; SIGTERM then makes the
+//! old process stop accepting, finish its in-flight transfers and exit,
+//! while the kernel hands every new connection to the new process.
+
+use socket2::{Domain, SockRef, Socket, Type};
+use std::io::{self, ErrorKind};
+use std::net::{TcpListener, ToSocketAddrs};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long a blocked accept waits before the loop rechecks for SIGTERM.
+/// Incoming connections still wake it immediately.
+pub const ACCEPT_POLL: Duration = Duration::from_millis(250);
+
+pub fn bind(address: &str, reuse_port: bool) -> io::Result<TcpListener> {
+    let address = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "address did not resolve"))?;
+    let socket = Socket::new(Domain::for_address(address), Type::STREAM, None)?;
+    // The same defaults TcpListener::bind uses.
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    if reuse_port {
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+        #[cfg(not(unix))]
+        return Err(io::Error::new(
+            ErrorKind::Unsupported,
+            "--reuse-port needs SO_REUSEPORT, which this platform lacks",
+        ));
+    }
+    socket.bind(&address.into())?;
+    socket.listen(128)?;
+    let listener: TcpListener = socket.into();
+    SockRef::from(&listener).set_read_timeout(Some(ACCEPT_POLL))?;
+    Ok(listener)
+}
+
+/// Returns a flag that the first SIGTERM sets. A second SIGTERM, for
+/// transfers that would take too long to drain, exits at once.
+pub fn on_terminate() -> io::Result<Arc<AtomicBool>> {
+    let terminating = Arc::new(AtomicBool::new(false));
+    #[cfg(unix)]
+    {
+        use signal_hook::consts::SIGTERM;
+        signal_hook::flag::register_conditional_shutdown(SIGTERM, 1, Arc::clone(&terminating))?;
+        signal_hook::flag::register(SIGTERM, Arc::clone(&terminating))?;
+    }
+    Ok(terminating)
+}
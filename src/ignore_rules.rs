@@ -0,0 +1,306 @@
+/*
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ * More licensing information can be found in the project LICENSE file
+ * Author: Harshit Jain
+ * Email: reach@harsh1998.dev
+ */
+
+//! Hides paths matched by ignore files. `.hdlignore` files are always
+//! honoured; `.gitignore` files only with `--use-gitignore`. Both use
+//! gitignore syntax, apply to the directory they sit in and everything
+//! below it, and can be nested, with deeper files taking precedence.
+//! Dotfiles such as `.env` or `.git` are also hidden entirely, or only
+//! kept out of listings, depending on [`HiddenFiles`].
+
+use crate::access::ACCESS_FILE;
+use ignore::gitignore::Gitignore;
+use ignore::Match;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+const HDLIGNORE: &str = ".hdlignore";
+const GITIGNORE: &str = ".gitignore";
+
+/// What happens to files and directories whose names start with `.`.
+#[derive(Clone, Copy, PartialEq)]
+pub enum HiddenFiles {
+    /// Listed and served like any other (`--show-hidden`).
+    Show,
+    /// Left out of listings, but served when asked for by name
+    /// (`--unlist-hidden`).
+    Unlisted,
+    /// Neither listed nor served.
+    Deny,
+}
+
+pub struct IgnoreRules {
+    root: PathBuf,
+    use_gitignore: bool,
+    hidden_files: HiddenFiles,
+    /// Parsed ignore files keyed by path, reparsed when their mtime changes.
+    /// Only files that exist are kept, so requests for made-up paths cannot
+    /// grow the map.
+    matchers: Mutex<HashMap<PathBuf, Matcher>>,
+}
+
+struct Matcher {
+    modified: SystemTime,
+    gitignore: Arc<Gitignore>,
+}
+
+impl IgnoreRules {
+    pub fn new(root: PathBuf, use_gitignore: bool, hidden_files: HiddenFiles) -> Self {
+        IgnoreRules {
+            root,
+            use_gitignore,
+            hidden_files,
+            matchers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether `path` (inside the served root) must be neither listed nor
+    /// served. A path inside an ignored directory is ignored too, as with
+    /// git, and no deeper rule can bring it back.
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        let relative = match path.strip_prefix(&self.root) {
+            Ok(relative) => normalize(relative),
+            Err(_) => return false,
+        };
+        let components: Vec<_> = relative.components().collect();
+        let mut directory = self.root.clone();
+        for (index, component) in components.iter().enumerate() {
+            let candidate = directory.join(component);
+            let is_dir = index + 1 < components.len() || candidate.is_dir();
+            if self.is_hidden_name(component.as_os_str().to_str().unwrap_or_default(), is_dir)
+                || self.matches(&directory, &candidate, is_dir)
+            {
+                return true;
+            }
+            directory = candidate;
+        }
+        false
+    }
+
+    /// Whether a listing should leave `path` out: everything ignored, and
+    /// dotfiles unless `--show-hidden` is given.
+    pub fn is_unlisted(&self, path: &Path) -> bool {
+        self.is_ignored(path)
+            || (self.hidden_files == HiddenFiles::Unlisted
+                && path
+                    .file_name()
+                    .is_some_and(|name| is_dotfile(&name.to_string_lossy())))
+    }
+
+    /// The server's own control files are never exposed, and neither is
+    /// the repository metadata of a checkout served with `--use-gitignore`.
+    fn is_hidden_name(&self, name: &str, is_dir: bool) -> bool {
+        // A case-insensitive filesystem would serve `.HDL_ACCESS` as well.
+        name.eq_ignore_ascii_case(HDLIGNORE)
+            || name.eq_ignore_ascii_case(ACCESS_FILE)
+            || (self.use_gitignore
+                && (name.eq_ignore_ascii_case(GITIGNORE)
+                    || (is_dir && name.eq_ignore_ascii_case(".git"))))
+            || (self.hidden_files == HiddenFiles::Deny && is_dotfile(name))
+    }
+
+    /// Consults the ignore files from `directory` up to the root; the
+    /// nearest file with a matching rule decides. Directories that do not
+    /// exist cannot hold one and are skipped.
+    fn matches(&self, directory: &Path, candidate: &Path, is_dir: bool) -> bool {
+        for ancestor in directory
+            .ancestors()
+            .skip_while(|ancestor| !ancestor.is_dir())
+        {
+            if !ancestor.starts_with(&self.root) {
+                break;
+            }
+            // .hdlignore is consulted first so it can override .gitignore.
+            let names: &[&str] = if self.use_gitignore {
+                &[HDLIGNORE, GITIGNORE]
+            } else {
+                &[HDLIGNORE]
+            };
+            for name in names {
+                let Some(gitignore) = self.matcher(&ancestor.join(name)) else {
+                    continue;
+                };
+                match gitignore.matched(candidate, is_dir) {
+                    Match::Ignore(_) => return true,
+                    Match::Whitelist(_) => return false,
+                    Match::None => {}
+                }
+            }
+        }
+        false
+    }
+
+    fn matcher(&self, file: &Path) -> Option<Arc<Gitignore>> {
+        let modified = fs::metadata(file).and_then(|metadata| metadata.modified());
+        let mut matchers = self.matchers.lock().unwrap();
+        let Ok(modified) = modified else {
+            matchers.remove(file);
+            return None;
+        };
+        if let Some(matcher) = matchers.get(file) {
+            if matcher.modified == modified {
+                return Some(Arc::clone(&matcher.gitignore));
+            }
+        }
+
+        let (gitignore, error) = Gitignore::new(file);
+        if let Some(e) = error {
+            eprintln!("Error parsing ignore file {:?}: {}", file, e);
+        }
+        let gitignore = Arc::new(gitignore);
+        matchers.insert(
+            file.to_path_buf(),
+            Matcher {
+                modified,
+                gitignore: Arc::clone(&gitignore),
+            },
+        );
+        Some(gitignore)
+    }
+}
+
+/// Whether `name` is hidden by convention. `.well-known` (RFC 8615) is
+/// meant to be fetched, by ACME clients among others, so it never counts.
+fn is_dotfile(name: &str) -> bool {
+    name.starts_with('.') && name != ".well-known"
+}
+
+/// Resolves `.` and `..` lexically so a request cannot dodge a rule by
+/// spelling the path differently.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::Normal(name) => normalized.push(name),
+            _ => {}
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn write(root: &Path, relative: &str, contents: &str) {
+        let path = root.join(relative);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    fn rules(root: &TempDir, use_gitignore: bool) -> IgnoreRules {
+        IgnoreRules::new(root.path().to_path_buf(), use_gitignore, HiddenFiles::Show)
+    }
+
+    #[test]
+    fn nearest_rule_decides() {
+        let root = TempDir::new().unwrap();
+        write(root.path(), HDLIGNORE, "*.log\n");
+        write(root.path(), "logs/.hdlignore", "!keep.log\n");
+        let rules = rules(&root, false);
+
+        assert!(rules.is_ignored(&root.path().join("debug.log")));
+        assert!(rules.is_ignored(&root.path().join("other/debug.log")));
+        assert!(!rules.is_ignored(&root.path().join("logs/keep.log")));
+        assert!(rules.is_ignored(&root.path().join("logs/drop.log")));
+        assert!(!rules.is_ignored(&root.path().join("notes.txt")));
+    }
+
+    #[test]
+    fn ignored_directories_hide_everything_below() {
+        let root = TempDir::new().unwrap();
+        write(root.path(), HDLIGNORE, "private/\n");
+        write(root.path(), "private/.hdlignore", "!*\n");
+        write(root.path(), "private/sub/file.txt", "");
+        let rules = rules(&root, false);
+
+        // Not even a rule inside the directory brings it back, as with git.
+        assert!(rules.is_ignored(&root.path().join("private/sub/file.txt")));
+        // Nor does spelling the path differently.
+        assert!(rules.is_ignored(&root.path().join("public/../private/sub/file.txt")));
+        assert!(rules.is_ignored(&root.path().join("./private")));
+    }
+
+    #[test]
+    fn gitignore_only_when_asked() {
+        let root = TempDir::new().unwrap();
+        write(root.path(), GITIGNORE, "build/\n*.tmp\n");
+        write(root.path(), HDLIGNORE, "!keep.tmp\n");
+        fs::create_dir_all(root.path().join("build")).unwrap();
+        fs::create_dir_all(root.path().join(".git")).unwrap();
+
+        let plain = rules(&root, false);
+        assert!(!plain.is_ignored(&root.path().join("build")));
+        assert!(!plain.is_ignored(&root.path().join(GITIGNORE)));
+        assert!(!plain.is_ignored(&root.path().join(".git/config")));
+
+        let git = rules(&root, true);
+        assert!(git.is_ignored(&root.path().join("build")));
+        assert!(git.is_ignored(&root.path().join("a.tmp")));
+        // .hdlignore is read first, so it can override .gitignore.
+        assert!(!git.is_ignored(&root.path().join("keep.tmp")));
+        assert!(git.is_ignored(&root.path().join(GITIGNORE)));
+        assert!(git.is_ignored(&root.path().join(".git/config")));
+    }
+
+    #[test]
+    fn control_files_never_served() {
+        let root = TempDir::new().unwrap();
+        let rules = rules(&root, false);
+        for name in [HDLIGNORE, ACCESS_FILE, ".HDL_ACCESS", "sub/.HdlIgnore"] {
+            assert!(rules.is_ignored(&root.path().join(name)), "{}", name);
+        }
+        // Paths outside the root are not for these rules to judge.
+        assert!(!rules.is_ignored(Path::new("/elsewhere/.hdl_access")));
+    }
+
+    #[test]
+    fn edited_files_are_reread() {
+        let root = TempDir::new().unwrap();
+        write(root.path(), HDLIGNORE, "*.log\n");
+        let rules = rules(&root, false);
+        assert!(rules.is_ignored(&root.path().join("a.log")));
+
+        fs::remove_file(root.path().join(HDLIGNORE)).unwrap();
+        assert!(!rules.is_ignored(&root.path().join("a.log")));
+        assert!(rules.matchers.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn dotfiles_by_mode() {
+        let root = TempDir::new().unwrap();
+        let env = root.path().join("app/.env");
+        let inside = root.path().join(".cache/data.txt");
+        let known = root.path().join(".well-known/acme-challenge/token");
+        let rules = |hidden_files| IgnoreRules::new(root.path().to_path_buf(), false, hidden_files);
+
+        let show = rules(HiddenFiles::Show);
+        assert!(!show.is_ignored(&env) && !show.is_unlisted(&env));
+
+        let unlisted = rules(HiddenFiles::Unlisted);
+        assert!(!unlisted.is_ignored(&env));
+        assert!(unlisted.is_unlisted(&env));
+        // Only the dotfile itself is kept out of its listing; what is inside
+        // a hidden directory can still be listed by a client that opens it.
+        assert!(!unlisted.is_unlisted(&inside));
+
+        let deny = rules(HiddenFiles::Deny);
+        assert!(deny.is_ignored(&env));
+        assert!(deny.is_ignored(&inside));
+
+        for rules in [show, unlisted, deny] {
+            assert!(!rules.is_ignored(&known) && !rules.is_unlisted(&known));
+        }
+    }
+}
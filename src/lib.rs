@@ -0,0 +1,12 @@
+/*
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ * More licensing information can be found in the project LICENSE file
+ * Author: Harshit Jain
+ * Email: reach@harsh1998.dev
+ */
+
+//! Library half of hdl_sv. It holds the pieces that are useful outside the
+//! server binary, such as the request parser, so fuzz harnesses can link
+//! against them.
+
+pub mod request;
@@ -0,0 +1,592 @@
+/*
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ * More licensing information can be found in the project LICENSE file
+ * Author: Harshit Jain
+ * Email: reach@harsh1998.dev
+ */
+
+//! Directory listings, as HTML for browsers, plain text for scripts or
+//! JSON for sync clients, narrowed down by `?q=` and `?filter=`.
+
+use crate::access::Access;
+use crate::ignore_rules::IgnoreRules;
+use crate::response::Response;
+use crate::{
+    archive, content_type, count_label, directory_validator, encode_query_value, escape_html,
+    escape_json, format_last_modified, is_not_modified, modified_secs, static_url, thumbs,
+    ServerConfig, Target,
+};
+use humansize::{file_size_opts as options, FileSize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// A directory: its first index file when it has one, and otherwise a
+/// listing in the format the query or the Accept header asks for, unless
+/// the access rules switch listings off.
+pub fn route<'a>(
+    target: &Target,
+    path: &Path,
+    access: &Access,
+    config: &ServerConfig,
+) -> Response<'a> {
+    // The first configured index file present in the directory replaces the listing.
+    let index_file = config
+        .index_files
+        .iter()
+        .map(|name| path.join(name))
+        .find(|candidate| candidate.is_file() && !target.ignore_rules.is_ignored(candidate))
+        .and_then(|index| fs::read(&index).ok().map(|contents| (index, contents)));
+    if let Some((index, contents)) = index_file {
+        return Response::Page {
+            content_type: content_type(&index),
+            headers: String::new(),
+            body: contents,
+        };
+    }
+    if !access.can_list(access.realm()) {
+        return Response::Error(403, "Forbidden", "Directory listing is disabled");
+    }
+
+    let query = target.request.query_map();
+    let format = match query.get("format").map(String::as_str) {
+        Some("txt") => ListingFormat::Text,
+        Some("json") => ListingFormat::Json,
+        _ => preferred_listing_format(target.request.header("Accept")),
+    };
+    let content_type = match format {
+        ListingFormat::Html => "text/html; charset=utf-8",
+        ListingFormat::Text => "text/plain; charset=utf-8",
+        ListingFormat::Json => "application/json",
+    };
+    let validator = directory_validator(
+        path,
+        access,
+        1,
+        target.request.query(),
+        content_type,
+        target.ignore_rules,
+        target.access_rules,
+    );
+    let headers = format!(
+        "{}Vary: Accept\r\n{}",
+        validator.headers(),
+        config.page_policy
+    );
+    if is_not_modified(target.request, &validator) {
+        return Response::NotModified(headers);
+    }
+    let filter = ListingFilter::from_query(&query);
+    let listing = match format {
+        ListingFormat::Html => {
+            generate_directory_listing(target.root, path, &filter, target.ignore_rules, &|entry| {
+                config.thumbnails.is_some()
+                    && thumbs::is_image(entry)
+                    && access.can_download(entry, target.extensions)
+            })
+        }
+        ListingFormat::Text => generate_text_listing(
+            path,
+            &filter,
+            target.ignore_rules,
+            query.contains_key("sizes"),
+        ),
+        ListingFormat::Json => {
+            generate_json_listing(target.root, path, &filter, target.ignore_rules)
+        }
+    };
+    // The directory may have gone, or be unreadable to the server.
+    match listing {
+        Ok(body) => Response::Page {
+            content_type,
+            headers,
+            body: body.into_bytes(),
+        },
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            Response::Error(404, "Not Found", "Directory not found")
+        }
+        Err(_) => Response::Error(403, "Forbidden", "Directory cannot be read"),
+    }
+}
+
+/// What a listing is narrowed down to: `?filter=` takes a glob and `?q=`
+/// a case-insensitive substring of the name. Entries must match both.
+struct ListingFilter<'a> {
+    glob: Option<&'a str>,
+    search: Option<&'a str>,
+}
+
+impl<'a> ListingFilter<'a> {
+    fn from_query(query: &'a HashMap<String, String>) -> Self {
+        let get = |key| {
+            query
+                .get(key)
+                .map(String::as_str)
+                .filter(|value| !value.is_empty())
+        };
+        ListingFilter {
+            glob: get("filter"),
+            search: get("q"),
+        }
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        self.glob.is_none_or(|glob| matches_glob(glob, name))
+            && self
+                .search
+                .is_none_or(|search| name.to_lowercase().contains(&search.to_lowercase()))
+    }
+}
+
+/// The sorted children of `path` a listing shows. Entries that cannot be
+/// read, such as dangling symlinks, are left out.
+fn listing_entries(
+    path: &Path,
+    filter: &ListingFilter,
+    ignore_rules: &IgnoreRules,
+) -> std::io::Result<Vec<PathBuf>> {
+    let mut entries: Vec<_> = fs::read_dir(path)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|entry| fs::metadata(entry).is_ok())
+        .collect();
+    entries.retain(|entry| !ignore_rules.is_unlisted(entry));
+    entries.retain(|entry| {
+        let name = entry.file_name().unwrap_or_default().to_string_lossy();
+        filter.matches(&name)
+    });
+    entries.sort();
+    Ok(entries)
+}
+
+/// `has_thumbnail` picks the entries shown with a `/_thumb/` preview.
+fn generate_directory_listing(
+    root: &Path,
+    path: &Path,
+    filter: &ListingFilter,
+    ignore_rules: &IgnoreRules,
+    has_thumbnail: &dyn Fn(&Path) -> bool,
+) -> std::io::Result<String> {
+    let entries = listing_entries(path, filter, ignore_rules)?;
+    let summary = summarize_directory(&entries);
+
+    // A link to every ancestor from the root down, so a visitor can jump
+    // up several levels; the directory being listed is plain text.
+    let names: Vec<String> = path
+        .strip_prefix(root)
+        .unwrap_or(Path::new(""))
+        .iter()
+        .map(|name| name.to_string_lossy().into_owned())
+        .collect();
+    // The page names the directory by its URL, never by where it sits on
+    // the server's disk.
+    let url_path: String = names
+        .iter()
+        .fold(String::from("/"), |url_path, name| url_path + name + "/");
+    let mut breadcrumbs = if names.is_empty() {
+        String::from("<li>Home</li>")
+    } else {
+        String::from(r#"<li><a href="/">Home</a></li>"#)
+    };
+    let mut link = String::from("/");
+    for (index, name) in names.iter().enumerate() {
+        link += &format!("{}/", encode_query_value(name));
+        if index + 1 == names.len() {
+            breadcrumbs += &format!("<li>{}</li>", escape_html(name));
+        } else {
+            breadcrumbs += &format!(
+                r#"<li><a href="{}">{}</a></li>"#,
+                escape_html(&link),
+                escape_html(name)
+            );
+        }
+    }
+
+    let html = format!(
+        r#"
+        <!DOCTYPE html>
+        <html lang="en">
+        <head>
+            <meta charset="UTF-8">
+            <meta name="viewport" content="width=device-width, initial-scale=1.0">
+            <title>Directory Listing for {}</title>
+            <!-- Bootstrap CSS -->
+            <link
+                href="https://stackpath.bootstrapcdn.com/bootstrap/5.3.0/css/bootstrap.min.css"
+                rel="stylesheet"
+            >
+            <link rel="stylesheet" href="{}">
+        </head>
+        <body>
+            <div class="container">
+                <h1 title="{}">Directory Listing</h1>
+                <ol class="breadcrumbs">{breadcrumbs}</ol>
+                <form class="search" method="get">
+                    <input class="filter" type="search" name="q" placeholder="Search names" value="{search}">
+                    <input class="filter" type="search" name="filter" placeholder="Filter, e.g. *.log" value="{filter}">
+                </form>
+                <table class="table table-hover">
+                    <thead>
+                        <tr>
+                            <th>Name</th>
+                            <th>Size</th>
+                            <th>Last Modified</th>
+                        </tr>
+                    </thead>
+                    <tbody>
+                        {}
+                    </tbody>
+                    <tfoot>
+                        <tr>
+                            <td>{summary_files}, {summary_directories}</td>
+                            <td>{summary_size}</td>
+                            <td></td>
+                        </tr>
+                    </tfoot>
+                </table>
+            </div>
+        </body>
+        </html>
+        "#,
+        escape_html(&url_path),
+        static_url("style.css"),
+        escape_html(&url_path),
+        entries
+            .iter()
+            .filter_map(|path| {
+                let metadata = fs::metadata(path).ok()?;
+                let file_size = metadata.len().file_size(options::BINARY).unwrap(); // Format file size
+                let last_modified_str = format_last_modified(&metadata);
+
+                let current_dir = path.parent().unwrap();
+
+                let relative_path = path.strip_prefix(current_dir).unwrap();
+                // Percent-encoded, so names with `#`, `?`, `%` or quotes
+                // neither break the link nor the attribute.
+                let href = encode_query_value(&relative_path.to_string_lossy());
+
+                // Archives get a second link that lists their members.
+                let browse = if metadata.is_file() && archive::Format::of(path).is_some() {
+                    format!("<a class=\"browse\" href=\"{}?browse\">browse</a>", href)
+                } else {
+                    String::new()
+                };
+                let thumbnail = if has_thumbnail(path) {
+                    format!(
+                        "<img class=\"thumb\" src=\"/_thumb{}{}\" loading=\"lazy\" alt=\"\">",
+                        escape_html(&link),
+                        href
+                    )
+                } else {
+                    String::new()
+                };
+
+                Some(format!(
+                    "<tr><td>{}<a href=\"{}\">{}</a>{}</td><td>{}</td><td>{}</td></tr>",
+                    thumbnail,
+                    href,
+                    escape_html(&path.file_name().unwrap().to_string_lossy()),
+                    browse,
+                    file_size,
+                    last_modified_str
+                ))
+            })
+            .collect::<String>(),
+        search = escape_html(filter.search.unwrap_or_default()),
+        filter = escape_html(filter.glob.unwrap_or_default()),
+        summary_files = count_label(summary.files, "file", "files"),
+        summary_directories = count_label(summary.directories, "directory", "directories"),
+        summary_size = summary.total_size.file_size(options::BINARY).unwrap(),
+    );
+    Ok(html)
+}
+
+/// One name per line, with a trailing `/` on directories, for piping into
+/// grep or xargs. With `sizes`, each line is the size in bytes (`-` for a
+/// directory), a tab, and the name.
+fn generate_text_listing(
+    path: &Path,
+    filter: &ListingFilter,
+    ignore_rules: &IgnoreRules,
+    sizes: bool,
+) -> std::io::Result<String> {
+    let mut text = String::new();
+    for entry in listing_entries(path, filter, ignore_rules)? {
+        let Ok(metadata) = fs::metadata(&entry) else {
+            continue;
+        };
+        // A newline in a name would otherwise read as two entries.
+        let name = entry
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .replace(['\n', '\r'], "?");
+        let slash = if metadata.is_dir() { "/" } else { "" };
+        if sizes {
+            let size = if metadata.is_dir() {
+                String::from("-")
+            } else {
+                metadata.len().to_string()
+            };
+            text += &format!("{}\t{}{}\n", size, name, slash);
+        } else {
+            text += &format!("{}{}\n", name, slash);
+        }
+    }
+    Ok(text)
+}
+
+/// The entries of a listing as JSON, for scripts that mirror or sync a
+/// directory without scraping HTML. `href` is the entry's absolute URL
+/// path, with a trailing `/` on directories, and `modified` is in seconds
+/// since the epoch as in `/_tree`.
+fn generate_json_listing(
+    root: &Path,
+    path: &Path,
+    filter: &ListingFilter,
+    ignore_rules: &IgnoreRules,
+) -> std::io::Result<String> {
+    let entries: Vec<String> = listing_entries(path, filter, ignore_rules)?
+        .iter()
+        .filter_map(|entry| {
+            let metadata = fs::metadata(entry).ok()?;
+            let name = entry.file_name().unwrap_or_default().to_string_lossy();
+            let mut href: String = entry
+                .strip_prefix(root)
+                .unwrap_or(entry)
+                .iter()
+                .map(|segment| format!("/{}", encode_query_value(&segment.to_string_lossy())))
+                .collect();
+            let (kind, size) = if metadata.is_dir() {
+                href.push('/');
+                ("directory", String::from("null"))
+            } else {
+                ("file", metadata.len().to_string())
+            };
+            Some(format!(
+                r#"{{"name":"{}","type":"{}","href":"{}","size":{},"modified":{}}}"#,
+                escape_json(&name),
+                kind,
+                escape_json(&href),
+                size,
+                modified_secs(&metadata)
+            ))
+        })
+        .collect();
+    Ok(format!(r#"{{"entries":[{}]}}"#, entries.join(",")))
+}
+
+/// The renderings a directory listing comes in.
+#[derive(Clone, Copy)]
+enum ListingFormat {
+    Html,
+    Text,
+    Json,
+}
+
+/// The listing format the Accept header ranks highest. Each type takes the
+/// quality of its most specific matching range, and HTML wins ties, so
+/// curl's default `*/*` still gets HTML.
+fn preferred_listing_format(accept: Option<&str>) -> ListingFormat {
+    let Some(accept) = accept else {
+        return ListingFormat::Html;
+    };
+    let quality = |media_type: &str| {
+        let (main_type, _) = media_type.split_once('/').unwrap_or_default();
+        let mut best: Option<(u8, f32)> = None;
+        for range in accept.split(',') {
+            let mut params = range.split(';').map(str::trim);
+            let name = params.next().unwrap_or_default().to_ascii_lowercase();
+            let specificity = if name == media_type {
+                2
+            } else if name.strip_suffix("/*") == Some(main_type) {
+                1
+            } else if name == "*/*" {
+                0
+            } else {
+                continue;
+            };
+            let q = params
+                .filter_map(|param| param.strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if best.is_none_or(|(best, _)| specificity > best) {
+                best = Some((specificity, q));
+            }
+        }
+        best.map_or(0.0, |(_, q)| q)
+    };
+    let html = quality("text/html");
+    let text = quality("text/plain");
+    let json = quality("application/json");
+    if json > html && json >= text {
+        ListingFormat::Json
+    } else if text > html {
+        ListingFormat::Text
+    } else {
+        ListingFormat::Html
+    }
+}
+
+/// Aggregate figures for the direct children of one directory.
+struct DirectorySummary {
+    files: usize,
+    directories: usize,
+    /// Combined size of the files; subdirectories are not descended into.
+    total_size: u64,
+}
+
+fn summarize_directory(entries: &[PathBuf]) -> DirectorySummary {
+    let mut summary = DirectorySummary {
+        files: 0,
+        directories: 0,
+        total_size: 0,
+    };
+    for metadata in entries.iter().filter_map(|entry| fs::metadata(entry).ok()) {
+        if metadata.is_dir() {
+            summary.directories += 1;
+        } else {
+            summary.files += 1;
+            summary.total_size += metadata.len();
+        }
+    }
+    summary
+}
+
+/// Case-insensitive shell-style match where `*` is any run of characters
+/// and `?` is exactly one.
+fn matches_glob(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let name: Vec<char> = name.to_lowercase().chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where to resume if the most recent `*` has to swallow one more character.
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            n = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ignore_rules::HiddenFiles;
+
+    const NO_FILTER: ListingFilter = ListingFilter {
+        glob: None,
+        search: None,
+    };
+
+    #[test]
+    fn pages_name_the_url_not_the_disk_path() {
+        let scratch = tempfile::tempdir().unwrap();
+        let root = scratch.path().canonicalize().unwrap();
+        let directory = root.join("docs & <notes>");
+        fs::create_dir(&directory).unwrap();
+        let ignore_rules = IgnoreRules::new(root.clone(), false, HiddenFiles::Deny);
+        let page = |path: &Path| {
+            generate_directory_listing(&root, path, &NO_FILTER, &ignore_rules, &|_| false).unwrap()
+        };
+
+        let listing = page(&directory);
+        assert!(listing.contains("<title>Directory Listing for /docs &amp; &lt;notes&gt;/</title>"));
+        assert!(listing.contains(r#"<h1 title="/docs &amp; &lt;notes&gt;/">"#));
+        assert!(!listing.contains(&*root.to_string_lossy()));
+        assert!(page(&root).contains("<title>Directory Listing for /</title>"));
+    }
+
+    #[test]
+    fn globs_match_whole_names() {
+        assert!(matches_glob("*.log", "app.log"));
+        assert!(matches_glob("*.log", ".log"));
+        assert!(!matches_glob("*.log", "app.log.1"));
+        assert!(matches_glob("app-?.txt", "app-1.txt"));
+        assert!(!matches_glob("app-?.txt", "app-12.txt"));
+        assert!(!matches_glob("app-?.txt", "app-.txt"));
+        assert!(matches_glob("**", ""));
+        assert!(matches_glob("", ""));
+        assert!(!matches_glob("", "a"));
+    }
+
+    #[test]
+    fn globs_backtrack() {
+        // The first `a` is not the one the `*` must stop at.
+        assert!(matches_glob("*a*b", "xaxxab"));
+        assert!(matches_glob("*a*b", "aab"));
+        assert!(!matches_glob("*a*b", "xabx"));
+        assert!(matches_glob("*.tar.*", "backup.2024.tar.gz"));
+        assert!(matches_glob("a*?c", "abbc"));
+        assert!(!matches_glob("a*?c", "ac"));
+    }
+
+    #[test]
+    fn globs_ignore_case() {
+        assert!(matches_glob("*.JPG", "holiday.jpg"));
+        assert!(matches_glob("readme*", "README.md"));
+        assert!(matches_glob("ÉTÉ?", "été1"));
+    }
+
+    fn preferred(accept: Option<&str>) -> &'static str {
+        match preferred_listing_format(accept) {
+            ListingFormat::Html => "html",
+            ListingFormat::Text => "text",
+            ListingFormat::Json => "json",
+        }
+    }
+
+    #[test]
+    fn accept_picks_the_listing_format() {
+        assert_eq!(preferred(None), "html");
+        assert_eq!(preferred(Some("application/json")), "json");
+        assert_eq!(preferred(Some("TEXT/PLAIN")), "text");
+        assert_eq!(preferred(Some("text/html;q=0.5, application/json")), "json");
+        assert_eq!(
+            preferred(Some("application/json;q=0.9, text/plain")),
+            "text"
+        );
+    }
+
+    #[test]
+    fn wildcards_rank_below_exact_types() {
+        // curl sends `*/*`, which should still get a page.
+        assert_eq!(preferred(Some("*/*")), "html");
+        assert_eq!(preferred(Some("text/*")), "html");
+        assert_eq!(preferred(Some("*/*;q=0.1, application/json")), "json");
+        // The exact range decides even when a wildcard ranks higher.
+        assert_eq!(preferred(Some("text/html;q=0.2, */*")), "json");
+        assert_eq!(preferred(Some("text/plain;q=0.2, text/*")), "html");
+    }
+
+    #[test]
+    fn zero_quality_refuses_a_type() {
+        assert_eq!(preferred(Some("text/html;q=0, text/plain")), "text");
+        assert_eq!(preferred(Some("text/html; q=0, */*")), "json");
+        assert_eq!(preferred(Some("application/json;q=0, */*")), "html");
+        // Nothing acceptable at all still gets the page.
+        assert_eq!(preferred(Some("*/*;q=0")), "html");
+    }
+
+    #[test]
+    fn ties_favour_html_then_json() {
+        assert_eq!(preferred(Some("application/json, text/html")), "html");
+        assert_eq!(preferred(Some("text/plain, text/html;q=1.0")), "html");
+        assert_eq!(preferred(Some("text/plain, application/json")), "json");
+        assert_eq!(
+            preferred(Some("text/plain;q=0.5, application/json;q=0.5")),
+            "json"
+        );
+    }
+}
@@ -0,0 +1,344 @@
+/*
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ * More licensing information can be found in the project LICENSE file
+ * Author: Harshit Jain
+ * Email: reach@harsh1998.dev
+ */
+
+//! Caching mirror of an upstream HTTP(S) tree. Files missing locally are
+//! fetched from upstream, streamed to the client and written into the
+//! served directory as they go, so the next request is served from disk.
+
+use crate::access::Access;
+use crate::response::Response;
+use crate::scratch::{self, ScratchDir};
+use crate::{
+    content_type, encode_query_value, send_download, Attachment, ServerConfig, Target,
+    TransferStatus,
+};
+use chrono::DateTime;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How long a mirrored file is trusted before upstream is asked again.
+const REVALIDATE_INTERVAL: Duration = Duration::from_secs(60);
+/// How long upstream may send nothing before a fetch is given up.
+const UPSTREAM_READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct Mirror {
+    upstream: String,
+    agent: ureq::Agent,
+    /// Validators for files fetched by this process. Files absent from the
+    /// map were already in the directory and are never sent upstream.
+    validators: Mutex<HashMap<PathBuf, Validator>>,
+    temp_counter: AtomicU64,
+    /// Where bodies are written while in flight; beside the target if unset.
+    scratch: Option<ScratchDir>,
+    /// Longest a fetch may take, body included (`--mirror-timeout`).
+    timeout: Duration,
+}
+
+#[derive(Clone)]
+struct Validator {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    checked: Instant,
+}
+
+pub enum Fetch<'a> {
+    /// The local copy can be served as is.
+    Fresh,
+    /// Upstream does not have the file either.
+    NotFound,
+    /// A new body from upstream, cached to disk while it is read.
+    Body(CachingReader<'a>),
+}
+
+impl Mirror {
+    /// Each fetch is bounded by `timeout` overall, so an upstream that
+    /// stalls or trickles cannot hold a connection slot forever.
+    pub fn new(upstream: &str, scratch: Option<ScratchDir>, timeout: Duration) -> Self {
+        Mirror {
+            upstream: upstream.trim_end_matches('/').to_string(),
+            // An overall ureq timeout would replace the per-read one, so
+            // the deadline is kept by CachingReader instead.
+            agent: ureq::AgentBuilder::new()
+                .timeout_connect(Duration::from_secs(10))
+                .timeout_read(UPSTREAM_READ_TIMEOUT.min(timeout))
+                .build(),
+            validators: Mutex::new(HashMap::new()),
+            temp_counter: AtomicU64::new(0),
+            scratch,
+            timeout,
+        }
+    }
+
+    /// Decides whether `local` can be served directly or has to come from
+    /// upstream. `upstream_path` is the percent-encoded path below the
+    /// upstream root, starting with `/`, and is appended to its URL.
+    pub fn fetch(&self, upstream_path: &str, local: &Path) -> io::Result<Fetch<'_>> {
+        let deadline = Instant::now() + self.timeout;
+        let mut request = self.agent.get(&format!("{}{}", self.upstream, upstream_path));
+        if local.is_file() {
+            let validators = self.validators.lock().unwrap();
+            match validators.get(local) {
+                Some(validator) if validator.checked.elapsed() >= REVALIDATE_INTERVAL => {
+                    if let Some(etag) = &validator.etag {
+                        request = request.set("If-None-Match", etag);
+                    }
+                    if let Some(last_modified) = &validator.last_modified {
+                        request = request.set("If-Modified-Since", last_modified);
+                    }
+                }
+                _ => return Ok(Fetch::Fresh),
+            }
+        }
+
+        let response = match request.call() {
+            Ok(response) => response,
+            Err(ureq::Error::Status(404 | 410, _)) => return Ok(Fetch::NotFound),
+            Err(ureq::Error::Status(code, _)) => {
+                return Err(io::Error::other(format!("upstream returned {}", code)));
+            }
+            // ureq's own message includes the URL, credentials and all.
+            Err(ureq::Error::Transport(transport)) => {
+                return Err(io::Error::other(match transport.message() {
+                    Some(message) => format!("{}: {}", transport.kind(), message),
+                    None => transport.kind().to_string(),
+                }));
+            }
+        };
+        if response.status() == 304 {
+            if let Some(validator) = self.validators.lock().unwrap().get_mut(local) {
+                validator.checked = Instant::now();
+            }
+            return Ok(Fetch::Fresh);
+        }
+
+        let validator = Validator {
+            etag: response.header("ETag").map(str::to_string),
+            last_modified: response.header("Last-Modified").map(str::to_string),
+            checked: Instant::now(),
+        };
+        let length = response
+            .header("Content-Length")
+            .and_then(|length| length.parse().ok());
+
+        // The body goes to a temporary file and is moved into place only
+        // once complete, so no reader ever sees a partial file.
+        if let Some(parent) = local.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let (temp, temp_path) = match &self.scratch {
+            Some(scratch) => scratch.create(local, length)?,
+            None => {
+                let temp_path = local.with_file_name(format!(
+                    ".{}.{}-{}.part",
+                    local.file_name().unwrap_or_default().to_string_lossy(),
+                    std::process::id(),
+                    self.temp_counter.fetch_add(1, Ordering::Relaxed)
+                ));
+                (File::create(&temp_path)?, temp_path)
+            }
+        };
+        Ok(Fetch::Body(CachingReader {
+            mirror: self,
+            upstream: response.into_reader(),
+            length,
+            received: 0,
+            deadline,
+            temp,
+            temp_path,
+            local: local.to_path_buf(),
+            validator,
+            persisted: false,
+        }))
+    }
+}
+
+/// Serves `path` from upstream when the local copy is missing or out of
+/// date, caching it on the way. `None` leaves the request to the local
+/// files, which are current, or absent upstream too.
+pub fn route<'a>(
+    target: &Target,
+    path: &Path,
+    access: &Access,
+    config: &'a ServerConfig,
+) -> Option<Response<'a>> {
+    let mirror = config.mirror.as_ref()?;
+    // Only downloads are mirrored, and never for paths that try to climb
+    // out of the root, encoded (`%2e%2e`) or not. Upstream is asked for
+    // the path as resolved here, not the raw request target.
+    let climbs_out = Path::new(target.path)
+        .components()
+        .any(|component| component == Component::ParentDir);
+    if climbs_out || path.is_dir() || !access.can_download(path, target.extensions) {
+        return None;
+    }
+    let upstream_path: String = path
+        .strip_prefix(target.root)
+        .unwrap_or(Path::new(""))
+        .iter()
+        .map(|name| format!("/{}", encode_query_value(&name.to_string_lossy())))
+        .collect();
+    match mirror.fetch(&upstream_path, path) {
+        Ok(Fetch::Body(mut body)) => {
+            let path = path.to_path_buf();
+            let peer = target.peer.to_string();
+            Some(Response::Stream(Box::new(move |stream| {
+                let filename = path.file_name().unwrap_or_default().to_string_lossy();
+                let attachment = Attachment::new(&filename, content_type(&path), body.length);
+                let status =
+                    send_download(stream, &mut body, &attachment, &peer, config.max_transfer);
+                // A HEAD request leaves the body unread, so there is
+                // nothing complete to keep.
+                let complete = matches!(status, TransferStatus::Completed) && !stream.head_only;
+                if let Err(e) = body.finish(complete) {
+                    eprintln!("Error caching mirrored file {:?}: {}", path, e);
+                }
+            })))
+        }
+        Ok(Fetch::Fresh) | Ok(Fetch::NotFound) => None,
+        Err(e) => {
+            eprintln!("Error fetching {} from upstream: {}", upstream_path, e);
+            // A stale local copy beats no copy at all.
+            if path.exists() {
+                None
+            } else {
+                Some(Response::Error(502, "Bad Gateway", "Upstream fetch failed"))
+            }
+        }
+    }
+}
+
+/// Reads the upstream body, copying every byte into the cache file.
+pub struct CachingReader<'a> {
+    mirror: &'a Mirror,
+    upstream: Box<dyn Read + Send + Sync + 'static>,
+    pub length: Option<u64>,
+    received: u64,
+    /// When the fetch runs out of `--mirror-timeout`.
+    deadline: Instant,
+    temp: File,
+    temp_path: PathBuf,
+    local: PathBuf,
+    validator: Validator,
+    persisted: bool,
+}
+
+impl Read for CachingReader<'_> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        // The partial file goes when the reader is dropped, like any other
+        // transfer that did not complete.
+        if Instant::now() >= self.deadline {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "upstream fetch took longer than --mirror-timeout",
+            ));
+        }
+        let bytes_read = self.upstream.read(buffer)?;
+        self.temp.write_all(&buffer[..bytes_read])?;
+        self.received += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+
+impl CachingReader<'_> {
+    /// Moves the cached body into place when the whole file arrived.
+    /// Anything short of that is discarded when the reader is dropped.
+    pub fn finish(mut self, complete: bool) -> io::Result<()> {
+        if !complete || self.length.is_some_and(|length| length != self.received) {
+            return Ok(());
+        }
+        // Carry upstream's Last-Modified over to the copy so listings show it.
+        if let Some(modified) = self
+            .validator
+            .last_modified
+            .as_deref()
+            .and_then(|date| DateTime::parse_from_rfc2822(date).ok())
+        {
+            self.temp.set_modified(SystemTime::from(modified))?;
+        }
+        scratch::persist(&self.temp_path, &self.local)?;
+        self.persisted = true;
+        self.mirror
+            .validators
+            .lock()
+            .unwrap()
+            .insert(self.local.clone(), self.validator.clone());
+        Ok(())
+    }
+}
+
+impl Drop for CachingReader<'_> {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = fs::remove_file(&self.temp_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// An upstream that promises a large body and then sends it a byte at
+    /// a time, never stalling long enough for the read timeout to fire.
+    fn trickling_upstream() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            let mut stream = reader.into_inner();
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 1000\r\n\r\n");
+            for _ in 0..1000 {
+                if stream.write_all(b"x").is_err() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(20));
+            }
+        });
+        format!("http://{}", address)
+    }
+
+    #[test]
+    fn deadline_abandons_trickling_fetch() {
+        let directory = tempfile::tempdir().unwrap();
+        let mirror = Mirror::new(&trickling_upstream(), None, Duration::from_millis(300));
+        let Fetch::Body(mut body) = mirror
+            .fetch("/file", &directory.path().join("file"))
+            .unwrap()
+        else {
+            panic!("expected a body from upstream");
+        };
+
+        let started = Instant::now();
+        let mut buffer = [0; 64];
+        let error = loop {
+            match body.read(&mut buffer) {
+                Ok(0) => panic!("upstream body ended early"),
+                Ok(_) => {}
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(body.temp_path.exists());
+
+        drop(body);
+        assert_eq!(fs::read_dir(directory.path()).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,266 @@
+/*
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ * More licensing information can be found in the project LICENSE file
+ * Author: Harshit Jain
+ * Email: reach@harsh1998.dev
+ */
+
+//! Part manifests for parallel download clients: a file is split into
+//! fixed-size byte ranges, each with its own SHA-256, so segments fetched
+//! separately can be verified before they are stitched back together.
+
+use crate::file_cache::FileCache;
+use crate::response::Response;
+use crate::{encode_path, escape_json, resolve_request_path, to_hex, ServerConfig, Target};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub const DEFAULT_PART_SIZE: u64 = 64 * 1024 * 1024;
+/// Smaller parts would make the manifest larger than it is useful.
+pub const MIN_PART_SIZE: u64 = 64 * 1024;
+pub const MAX_PART_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// Manifests kept in memory. A manifest of a large file split into small
+/// parts runs to megabytes.
+const MAX_CACHED: usize = 64;
+
+/// Manifests keyed by file and part size, regenerated whenever the file's
+/// size or modification time changes.
+pub struct Cache {
+    manifests: FileCache<(PathBuf, u64), Manifest>,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Cache {
+            manifests: FileCache::new(MAX_CACHED),
+        }
+    }
+}
+
+pub struct Manifest {
+    pub length: u64,
+    pub part_size: u64,
+    pub sha256: String,
+    /// Hex SHA-256 of each part, in order.
+    pub parts: Vec<String>,
+}
+
+impl Cache {
+    pub fn manifest(&self, path: &Path, part_size: u64) -> io::Result<Arc<Manifest>> {
+        self.manifests
+            .get_or_generate((path.to_path_buf(), part_size), path, |_| {
+                generate(path, part_size)
+            })
+    }
+}
+
+fn generate(path: &Path, part_size: u64) -> io::Result<Manifest> {
+    let mut file = File::open(path)?;
+    // Counted rather than taken from the metadata, so the manifest stays
+    // self-consistent even if the file is written to while it is hashed.
+    let mut length = 0;
+    let mut whole = Sha256::new();
+    let mut parts = Vec::new();
+    let mut buffer = vec![0; 1024 * 1024];
+    loop {
+        let mut part = Sha256::new();
+        let mut remaining = part_size;
+        while remaining > 0 {
+            let wanted = remaining.min(buffer.len() as u64) as usize;
+            let bytes_read = file.read(&mut buffer[..wanted])?;
+            if bytes_read == 0 {
+                break;
+            }
+            part.update(&buffer[..bytes_read]);
+            whole.update(&buffer[..bytes_read]);
+            remaining -= bytes_read as u64;
+            length += bytes_read as u64;
+        }
+        if remaining == part_size {
+            break;
+        }
+        parts.push(to_hex(&part.finalize()));
+        if remaining > 0 {
+            break;
+        }
+    }
+
+    Ok(Manifest {
+        length,
+        part_size,
+        sha256: to_hex(&whole.finalize()),
+        parts,
+    })
+}
+
+/// Parses a part size such as `65536`, `512K`, `64M` or `1G` (binary
+/// multiples, case-insensitive, optional trailing `B`). Only powers of two
+/// from `MIN_PART_SIZE` to `MAX_PART_SIZE` are accepted, which keeps the
+/// number of manifests one file can have cached small.
+pub fn parse_size(text: &str) -> Option<u64> {
+    let text = text.trim().to_ascii_uppercase();
+    let text = text.strip_suffix('B').unwrap_or(&text);
+    let (digits, multiplier) = match text.chars().last()? {
+        'K' => (&text[..text.len() - 1], 1024),
+        'M' => (&text[..text.len() - 1], 1024 * 1024),
+        'G' => (&text[..text.len() - 1], 1024 * 1024 * 1024),
+        _ => (text, 1),
+    };
+    digits
+        .parse::<u64>()
+        .ok()?
+        .checked_mul(multiplier)
+        .filter(|size| size.is_power_of_two() && (MIN_PART_SIZE..=MAX_PART_SIZE).contains(size))
+}
+
+/// `/_parts/<file>?size=N`: the part manifest of a file the client may
+/// download, as JSON.
+pub fn route<'a>(target: &Target, config: &ServerConfig) -> Response<'a> {
+    let part_size = match target
+        .request
+        .query_map()
+        .get("size")
+        .map(|size| parse_size(size))
+    {
+        None => DEFAULT_PART_SIZE,
+        Some(Some(size)) => size,
+        Some(None) => {
+            return Response::Error(
+                400,
+                "Bad Request",
+                "Part size must be a power of two from 64K to 1G",
+            );
+        }
+    };
+    let path = resolve_request_path(
+        target.root,
+        // Route prefixes have no escapes, so the decoded path starts with
+        // them too.
+        &target.path["/_parts/".len()..],
+        config.normalization,
+    );
+    let access = target.access_rules.resolve_path(&path);
+    if !path.is_file() || target.ignore_rules.is_ignored(&path) {
+        return Response::Error(404, "Not Found", "File not found");
+    }
+    if let Err(response) = target.authorize(&access, config) {
+        return response;
+    }
+    if !access.can_download(&path, target.extensions) {
+        return Response::Error(403, "Forbidden", "Only allowed files can be downloaded");
+    }
+    match config.parts_cache.manifest(&path, part_size) {
+        Ok(manifest) => Response::Page {
+            content_type: "application/json",
+            headers: String::new(),
+            body: generate_parts_json(target.root, &path, &manifest).into_bytes(),
+        },
+        Err(e) => {
+            eprintln!("Error generating part manifest for {:?}: {}", path, e);
+            Response::Error(404, "Not Found", "File not found")
+        }
+    }
+}
+
+/// Lists every part as an offset, a length, the matching `Range` header
+/// value and the part's SHA-256, alongside the hash of the whole file.
+fn generate_parts_json(root: &Path, path: &Path, manifest: &Manifest) -> String {
+    let href = format!("/{}", encode_path(path.strip_prefix(root).unwrap_or(path)));
+    let parts: Vec<String> = manifest
+        .parts
+        .iter()
+        .enumerate()
+        .map(|(index, sha256)| {
+            let offset = index as u64 * manifest.part_size;
+            let length = manifest.part_size.min(manifest.length - offset);
+            format!(
+                r#"{{"index":{},"offset":{},"length":{},"range":"bytes={}-{}","sha256":"{}"}}"#,
+                index,
+                offset,
+                length,
+                offset,
+                offset + length - 1,
+                sha256
+            )
+        })
+        .collect();
+    format!(
+        r#"{{"path":"{}","size":{},"part_size":{},"sha256":"{}","parts":[{}]}}"#,
+        escape_json(&href),
+        manifest.length,
+        manifest.part_size,
+        manifest.sha256,
+        parts.join(",")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const K: u64 = 1024;
+
+    #[test]
+    fn part_sizes_parse() {
+        assert_eq!(parse_size("65536"), Some(64 * K));
+        assert_eq!(parse_size(" 512k "), Some(512 * K));
+        assert_eq!(parse_size("64MB"), Some(64 * K * K));
+        assert_eq!(parse_size("1G"), Some(K * K * K));
+        for size in [
+            "",
+            "B",
+            "32K",
+            "2G",
+            "100K",
+            "-64K",
+            "64KK",
+            "1.5M",
+            "99999999999G",
+        ] {
+            assert_eq!(parse_size(size), None, "{}", size);
+        }
+    }
+
+    #[test]
+    fn parts_cover_the_file() {
+        let scratch = tempfile::tempdir().unwrap();
+        let root = scratch.path();
+        let contents: Vec<u8> = (0..2 * MIN_PART_SIZE + 10).map(|i| i as u8).collect();
+        fs::create_dir(root.join("iso")).unwrap();
+        let path = root.join("iso/disk 1.img");
+        fs::write(&path, &contents).unwrap();
+
+        let manifest = generate(&path, MIN_PART_SIZE).unwrap();
+        assert_eq!(manifest.length, contents.len() as u64);
+        assert_eq!(manifest.sha256, to_hex(&Sha256::digest(&contents)));
+        let chunks: Vec<String> = contents
+            .chunks(MIN_PART_SIZE as usize)
+            .map(|chunk| to_hex(&Sha256::digest(chunk)))
+            .collect();
+        assert_eq!(manifest.parts, chunks);
+
+        let json = generate_parts_json(root, &path, &manifest);
+        assert!(json.starts_with(r#"{"path":"/iso/disk%201.img","size":131082,"#));
+        assert!(json
+            .contains(r#"{"index":2,"offset":131072,"length":10,"range":"bytes=131072-131081","#));
+    }
+
+    #[test]
+    fn part_boundaries() {
+        let scratch = tempfile::tempdir().unwrap();
+        let path = scratch.path().join("a.bin");
+        fs::write(&path, vec![7; 2 * MIN_PART_SIZE as usize]).unwrap();
+        // No empty trailing part when the file ends on a boundary.
+        assert_eq!(generate(&path, MIN_PART_SIZE).unwrap().parts.len(), 2);
+
+        fs::write(&path, b"").unwrap();
+        let manifest = generate(&path, MIN_PART_SIZE).unwrap();
+        assert!(manifest.parts.is_empty());
+        assert_eq!(manifest.sha256, to_hex(&Sha256::digest(b"")));
+    }
+}
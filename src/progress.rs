@@ -0,0 +1,165 @@
+/*
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ * More licensing information can be found in the project LICENSE file
+ * Author: Harshit Jain
+ * Email: reach@harsh1998.dev
+ */
+
+//! Live progress of the downloads in flight (`--progress-events`), which
+//! `/_events` streams as Server-Sent Events for the web UI or external
+//! dashboards.
+
+use crate::response::Response;
+use crate::{Client, ServerConfig};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Every download currently being sent.
+#[derive(Default)]
+pub struct Transfers {
+    next_id: AtomicU64,
+    active: Mutex<BTreeMap<u64, Arc<Transfer>>>,
+    closing: AtomicBool,
+}
+
+struct Transfer {
+    path: String,
+    peer: String,
+    length: Option<u64>,
+    sent: AtomicU64,
+}
+
+impl Transfers {
+    /// Marks the server as shutting down, so event streams end once the
+    /// downloads still draining are done instead of holding it open.
+    pub fn close(&self) {
+        self.closing.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether an event stream should stop.
+    pub fn is_finished(&self) -> bool {
+        self.closing.load(Ordering::SeqCst) && self.active.lock().unwrap().is_empty()
+    }
+
+    /// The data line of one event: every transfer in flight, in the order
+    /// they started.
+    pub fn event(&self) -> String {
+        let transfers: Vec<String> = self
+            .active
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, transfer)| {
+                let sent = transfer.sent.load(Ordering::Relaxed);
+                let (length, percent) = match transfer.length {
+                    Some(length) => (
+                        length.to_string(),
+                        format!("{:.1}", sent as f64 * 100.0 / length.max(1) as f64),
+                    ),
+                    None => (String::from("null"), String::from("null")),
+                };
+                format!(
+                    r#"{{"id":{},"path":"{}","peer":"{}","bytes":{},"length":{},"percent":{}}}"#,
+                    id,
+                    crate::escape_json(&transfer.path),
+                    crate::escape_json(&transfer.peer),
+                    sent,
+                    length,
+                    percent
+                )
+            })
+            .collect();
+        format!("data: [{}]\n\n", transfers.join(","))
+    }
+}
+
+/// Where the downloads of one request report to, along with the request
+/// path they are listed under.
+pub struct Reporter {
+    transfers: Arc<Transfers>,
+    path: String,
+}
+
+impl Reporter {
+    pub fn new(transfers: &Arc<Transfers>, path: String) -> Self {
+        Reporter {
+            transfers: Arc::clone(transfers),
+            path,
+        }
+    }
+
+    /// Lists a download until the returned guard is dropped.
+    pub fn start(&self, peer: &str, length: Option<u64>) -> Progress {
+        let id = self.transfers.next_id.fetch_add(1, Ordering::Relaxed);
+        let transfer = Arc::new(Transfer {
+            path: self.path.clone(),
+            peer: peer.to_string(),
+            length,
+            sent: AtomicU64::new(0),
+        });
+        self.transfers
+            .active
+            .lock()
+            .unwrap()
+            .insert(id, Arc::clone(&transfer));
+        Progress {
+            transfers: Arc::clone(&self.transfers),
+            id,
+            transfer,
+        }
+    }
+}
+
+/// One listed download; dropping it takes the download off the list.
+pub struct Progress {
+    transfers: Arc<Transfers>,
+    id: u64,
+    transfer: Arc<Transfer>,
+}
+
+impl Progress {
+    /// Records how many body bytes have reached the client so far.
+    pub fn set_sent(&self, bytes: u64) {
+        self.transfer.sent.store(bytes, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        self.transfers.active.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// How often `/_events` reports, and looks for a client that left.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// `/_events`, with --progress-events; without it the path is left to be
+/// served like any other.
+pub fn route(config: &ServerConfig) -> Option<Response<'_>> {
+    let transfers = config.transfers.as_deref()?;
+    Some(Response::Stream(Box::new(move |stream| {
+        send_progress_events(stream, transfers)
+    })))
+}
+
+/// Streams the downloads in flight to an EventSource until the client goes
+/// away or the server shuts down.
+fn send_progress_events(stream: &mut Client, transfers: &Transfers) {
+    let head = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-store\r\n{}Connection: close\r\n\r\n",
+        stream.headers
+    );
+    if stream.write_all(head.as_bytes()).is_err() || stream.head_only {
+        return;
+    }
+    while !transfers.is_finished() && !crate::client_hung_up(stream) {
+        if stream.write_all(transfers.event().as_bytes()).is_err() {
+            return;
+        }
+        thread::sleep(PROGRESS_INTERVAL);
+    }
+}
@@ -0,0 +1,252 @@
+/*
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ * More licensing information can be found in the project LICENSE file
+ * Author: Harshit Jain
+ * Email: reach@harsh1998.dev
+ */
+
+//! `Range: bytes=` requests (RFC 9110 section 14), so interrupted
+//! downloads can be resumed and large files fetched in parallel segments.
+
+use std::collections::VecDeque;
+use std::io::{self, Cursor, ErrorKind, Read, Seek, SeekFrom};
+
+/// One satisfiable byte range of a representation `total` bytes long.
+/// `start` and `end` are inclusive, as in the header.
+#[derive(Clone, Copy)]
+pub struct ContentRange {
+    pub start: u64,
+    pub end: u64,
+    pub total: u64,
+}
+
+impl ContentRange {
+    pub fn length(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    /// The header line describing this range, CRLF-terminated.
+    pub fn header(&self) -> String {
+        format!(
+            "Content-Range: bytes {}-{}/{}\r\n",
+            self.start, self.end, self.total
+        )
+    }
+}
+
+/// More ranges than this in one request are treated as abuse (RFC 9110
+/// section 14.2 warns about many tiny or overlapping ranges), and the
+/// whole representation is sent instead.
+pub const MAX_RANGES: usize = 32;
+
+/// Parses a `Range` header against a representation of `total` bytes,
+/// keeping the satisfiable ranges in the order requested. A header that
+/// cannot be honoured (another unit, bad syntax, too many ranges) yields
+/// `None`, and the whole representation is sent instead, which RFC 9110
+/// allows. A valid header none of whose ranges overlap the representation
+/// yields no ranges at all, to be answered with 416.
+pub fn parse(header: &str, total: u64) -> Option<Vec<ContentRange>> {
+    let (unit, specs) = header.trim().split_once('=')?;
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+    let specs: Vec<&str> = specs
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+        .collect();
+    if specs.is_empty() || specs.len() > MAX_RANGES {
+        return None;
+    }
+    let mut ranges = Vec::new();
+    for spec in specs {
+        if let Some(range) = parse_spec(spec, total)? {
+            ranges.push(range);
+        }
+    }
+    Some(ranges)
+}
+
+/// The Content-Range line a 416 carries, telling the client the actual
+/// size so it can fetch the whole representation instead.
+pub fn unsatisfiable_header(total: u64) -> String {
+    format!("Content-Range: bytes */{}\r\n", total)
+}
+
+/// `None` for a malformed spec, `Some(None)` for a well-formed one that
+/// lies entirely past the end.
+fn parse_spec(spec: &str, total: u64) -> Option<Option<ContentRange>> {
+    let (first, last) = spec.split_once('-')?;
+    let (first, last) = (first.trim(), last.trim());
+    let (start, end) = if first.is_empty() {
+        // A suffix range: the last `last` bytes.
+        let suffix: u64 = last.parse().ok()?;
+        if suffix == 0 || total == 0 {
+            return Some(None);
+        }
+        (total.saturating_sub(suffix), total - 1)
+    } else {
+        let start: u64 = first.parse().ok()?;
+        let end = if last.is_empty() {
+            u64::MAX
+        } else {
+            last.parse::<u64>().ok().filter(|&end| end >= start)?
+        };
+        (start, end.min(total.saturating_sub(1)))
+    };
+    Some((start < total).then_some(ContentRange { start, end, total }))
+}
+
+/// Streams a `multipart/byteranges` body: each range of `source` preceded
+/// by its own part header, then the closing boundary.
+pub struct Multipart<R> {
+    source: R,
+    parts: VecDeque<Part>,
+}
+
+enum Part {
+    Text(Cursor<Vec<u8>>),
+    Bytes {
+        start: u64,
+        remaining: u64,
+        seeked: bool,
+    },
+}
+
+impl<R: Read + Seek> Multipart<R> {
+    /// Returns the body along with its exact length, for Content-Length.
+    pub fn new(
+        source: R,
+        ranges: &[ContentRange],
+        content_type: &str,
+        boundary: &str,
+    ) -> (Self, u64) {
+        let mut parts = VecDeque::new();
+        let mut length = 0;
+        for (index, range) in ranges.iter().enumerate() {
+            // Every delimiter but the first ends the previous part's data.
+            let separator = if index == 0 { "" } else { "\r\n" };
+            let head = format!(
+                "{}--{}\r\nContent-Type: {}\r\n{}\r\n",
+                separator,
+                boundary,
+                content_type,
+                range.header()
+            );
+            length += head.len() as u64 + range.length();
+            parts.push_back(Part::Text(Cursor::new(head.into_bytes())));
+            parts.push_back(Part::Bytes {
+                start: range.start,
+                remaining: range.length(),
+                seeked: false,
+            });
+        }
+        let tail = format!("\r\n--{}--\r\n", boundary);
+        length += tail.len() as u64;
+        parts.push_back(Part::Text(Cursor::new(tail.into_bytes())));
+        (Multipart { source, parts }, length)
+    }
+}
+
+impl<R: Read + Seek> Read for Multipart<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        while let Some(part) = self.parts.front_mut() {
+            let bytes_read = match part {
+                Part::Text(text) => text.read(buffer)?,
+                Part::Bytes {
+                    start,
+                    remaining,
+                    seeked,
+                } => {
+                    if *remaining == 0 {
+                        0
+                    } else {
+                        if !*seeked {
+                            self.source.seek(SeekFrom::Start(*start))?;
+                            *seeked = true;
+                        }
+                        let wanted = (*remaining).min(buffer.len() as u64) as usize;
+                        let bytes_read = self.source.read(&mut buffer[..wanted])?;
+                        if bytes_read == 0 {
+                            return Err(ErrorKind::UnexpectedEof.into());
+                        }
+                        *remaining -= bytes_read as u64;
+                        bytes_read
+                    }
+                }
+            };
+            if bytes_read > 0 || buffer.is_empty() {
+                return Ok(bytes_read);
+            }
+            self.parts.pop_front();
+        }
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spans(header: &str, total: u64) -> Option<Vec<(u64, u64)>> {
+        parse(header, total).map(|ranges| {
+            ranges
+                .iter()
+                .map(|range| (range.start, range.end))
+                .collect()
+        })
+    }
+
+    #[test]
+    fn suffix_ranges() {
+        assert_eq!(spans("bytes=-10", 100), Some(vec![(90, 99)]));
+        // A suffix longer than the file is the whole file.
+        assert_eq!(spans("bytes=-500", 100), Some(vec![(0, 99)]));
+        assert_eq!(spans("bytes=-0", 100), Some(vec![]));
+    }
+
+    #[test]
+    fn clamps_past_end() {
+        assert_eq!(spans("bytes=90-200", 100), Some(vec![(90, 99)]));
+        assert_eq!(spans("bytes=90-", 100), Some(vec![(90, 99)]));
+        // Entirely past the end: valid but unsatisfiable.
+        assert_eq!(spans("bytes=100-200", 100), Some(vec![]));
+        assert_eq!(spans("bytes=100-200, 0-0", 100), Some(vec![(0, 0)]));
+    }
+
+    #[test]
+    fn overlapping_ranges_kept_in_order() {
+        assert_eq!(
+            spans("bytes=50-60, 0-99, 55-", 100),
+            Some(vec![(50, 60), (0, 99), (55, 99)])
+        );
+    }
+
+    #[test]
+    fn too_many_ranges() {
+        let specs = |count: usize| {
+            (0..count)
+                .map(|index| format!("{}-{}", index, index))
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        let ranges = parse(&format!("bytes={}", specs(MAX_RANGES)), 100).unwrap();
+        assert_eq!(ranges.len(), MAX_RANGES);
+        assert!(parse(&format!("bytes={}", specs(MAX_RANGES + 1)), 100).is_none());
+    }
+
+    #[test]
+    fn empty_file_is_unsatisfiable() {
+        assert_eq!(spans("bytes=0-", 0), Some(vec![]));
+        assert_eq!(spans("bytes=0-0", 0), Some(vec![]));
+        assert_eq!(spans("bytes=-1", 0), Some(vec![]));
+    }
+
+    #[test]
+    fn malformed_headers() {
+        assert_eq!(spans("items=0-1", 100), None);
+        assert_eq!(spans("bytes=5-1", 100), None);
+        assert_eq!(spans("bytes=a-", 100), None);
+        assert_eq!(spans("bytes=", 100), None);
+    }
+}
@@ -0,0 +1,210 @@
+/*
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ * More licensing information can be found in the project LICENSE file
+ * Author: Harshit Jain
+ * Email: reach@harsh1998.dev
+ */
+
+//! Per-address request rate limiting (`--rate-limit`) with a token bucket,
+//! so a download manager opening several segment requests at once gets
+//! through while a client that keeps hammering the server does not.
+//! Addresses in `--rate-limit-exempt`, such as CI runners or health
+//! checkers on an internal network, are never throttled.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Past this many tracked addresses, those whose bucket has refilled are
+/// forgotten, since they are indistinguishable from new ones.
+const PRUNE_THRESHOLD: usize = 4096;
+
+pub struct RateLimiter {
+    /// Tokens added per second.
+    rate: f64,
+    /// Bucket size: how many requests can be made at once.
+    burst: f64,
+    exempt: Vec<Network>,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(per_minute: u32, burst: u32, exempt: Vec<Network>) -> Self {
+        RateLimiter {
+            rate: per_minute as f64 / 60.0,
+            burst: burst as f64,
+            exempt,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token for one request from `address`. When the bucket is
+    /// empty, returns how long until the next token.
+    pub fn check(&self, address: IpAddr) -> Result<(), Duration> {
+        if self.exempt.iter().any(|network| network.contains(address)) {
+            return Ok(());
+        }
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > PRUNE_THRESHOLD {
+            buckets.retain(|_, bucket| self.refilled(bucket, now) < self.burst);
+        }
+        let bucket = buckets.entry(address).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        bucket.tokens = self.refilled(bucket, now);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        }
+    }
+
+    /// How long until `address` has a token again, without taking one;
+    /// `None` when it has one now.
+    pub fn wait(&self, address: IpAddr) -> Option<Duration> {
+        let buckets = self.buckets.lock().unwrap();
+        let tokens = self.refilled(buckets.get(&address)?, Instant::now());
+        (tokens < 1.0).then(|| Duration::from_secs_f64((1.0 - tokens) / self.rate))
+    }
+
+    fn refilled(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.rate).min(self.burst)
+    }
+}
+
+/// An address block such as `10.0.0.0/8`; a bare address is a block of one.
+#[derive(Clone, Debug)]
+pub struct Network {
+    address: IpAddr,
+    prefix: u32,
+}
+
+impl Network {
+    pub fn contains(&self, address: IpAddr) -> bool {
+        // A dual-stack listener sees IPv4 clients as `::ffff:a.b.c.d`.
+        match (self.address, address.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix).unwrap_or(0);
+                u32::from(network) & mask == u32::from(address) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+                u128::from(network) & mask == u128::from(address) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Parses a `--rate-limit-exempt` entry: an address, or an address and a
+/// prefix length such as `10.0.0.0/8` or `fd00::/8`.
+pub fn parse_network(spec: &str) -> Result<Network, String> {
+    let (address, prefix) = match spec.trim().split_once('/') {
+        Some((address, prefix)) => (address, Some(prefix)),
+        None => (spec.trim(), None),
+    };
+    let address: IpAddr = address
+        .parse()
+        .map_err(|_| format!("{:?} is not an IP address", address))?;
+    let bits = if address.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix.map(str::parse::<u32>) {
+        None => bits,
+        Some(Ok(prefix)) if prefix <= bits => prefix,
+        Some(_) => return Err(format!("expected a prefix length from 0 to {}", bits)),
+    };
+    Ok(Network { address, prefix })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use std::thread;
+
+    const CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const OTHER: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+    #[test]
+    fn bursts_then_throttles() {
+        let limiter = RateLimiter::new(60, 3, Vec::new());
+        for _ in 0..3 {
+            assert!(limiter.check(CLIENT).is_ok());
+        }
+        let retry_after = limiter.check(CLIENT).unwrap_err();
+        assert!(retry_after > Duration::ZERO && retry_after <= Duration::from_secs(1));
+        // Each address has a bucket of its own.
+        assert!(limiter.check(OTHER).is_ok());
+    }
+
+    #[test]
+    fn buckets_refill() {
+        // Ten tokens a second, but never more than the burst.
+        let limiter = RateLimiter::new(600, 1, Vec::new());
+        assert!(limiter.check(CLIENT).is_ok());
+        assert!(limiter.check(CLIENT).is_err());
+        thread::sleep(Duration::from_millis(250));
+        assert!(limiter.check(CLIENT).is_ok());
+        assert!(limiter.check(CLIENT).is_err());
+    }
+
+    #[test]
+    fn waiting_takes_no_token() {
+        let limiter = RateLimiter::new(60, 1, Vec::new());
+        assert_eq!(limiter.wait(CLIENT), None);
+        assert!(limiter.check(CLIENT).is_ok());
+        let wait = limiter.wait(CLIENT).unwrap();
+        assert!(wait > Duration::ZERO && wait <= Duration::from_secs(1));
+        assert!(limiter.wait(CLIENT).is_some());
+        assert_eq!(limiter.wait(OTHER), None);
+    }
+
+    fn network(spec: &str) -> Network {
+        parse_network(spec).unwrap()
+    }
+
+    #[test]
+    fn networks_parse() {
+        assert!(network("10.0.0.0/8").contains(IpAddr::from([10, 1, 2, 3])));
+        assert!(!network("10.0.0.0/8").contains(IpAddr::from([11, 0, 0, 1])));
+        assert!(network(" 192.0.2.1 ").contains(CLIENT));
+        assert!(!network("192.0.2.1").contains(OTHER));
+        assert!(network("0.0.0.0/0").contains(OTHER));
+        assert!(network("fd00::/8").contains("fd12::1".parse().unwrap()));
+        assert!(!network("fd00::/8").contains("fe80::1".parse().unwrap()));
+        for spec in ["", "10.0.0.0/33", "fd00::/129", "10.0.0.0/x", "example.com"] {
+            assert!(parse_network(spec).is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn mapped_addresses_match_ipv4_networks() {
+        // A dual-stack listener sees IPv4 clients as `::ffff:a.b.c.d`.
+        let mapped = "::ffff:192.0.2.1".parse().unwrap();
+        assert!(network("192.0.2.0/24").contains(mapped));
+        assert!(!network("::/0").contains(mapped));
+        assert!(!network("192.0.2.0/24").contains("2001:db8::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn exempt_networks_are_never_throttled() {
+        let limiter = RateLimiter::new(60, 1, vec![network("192.0.2.0/31")]);
+        for _ in 0..5 {
+            assert!(limiter.check(CLIENT).is_ok());
+        }
+        assert!(limiter.buckets.lock().unwrap().is_empty());
+        let outsider = IpAddr::from([192, 0, 2, 2]);
+        assert!(limiter.check(outsider).is_ok());
+        assert!(limiter.check(outsider).is_err());
+    }
+}
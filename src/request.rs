@@ -0,0 +1,583 @@
+/*
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ * More licensing information can be found in the project LICENSE file
+ * Author: Harshit Jain
+ * Email: reach@harsh1998.dev
+ */
+
+//! Incremental HTTP/1.x request-head parser.
+//!
+//! Bytes are pushed into a [`Parser`] as they arrive. Each complete line is
+//! consumed as soon as its terminator is seen, so a head split across any
+//! number of TCP segments (even in the middle of a multi-byte UTF-8
+//! sequence) parses the same as one delivered in a single read. The parser
+//! itself does no I/O, so it can be driven directly by a fuzzer.
+
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::ops::Range;
+
+/// Upper bound on the request line plus all header lines.
+pub const MAX_HEAD_SIZE: usize = 8 * 1024;
+
+/// Default upper bound on the number of header lines.
+pub const MAX_HEADERS: usize = 100;
+
+/// How forgiving the parser is, and how large a head it accepts. The
+/// limits can only tighten [`MAX_HEAD_SIZE`], which bounds the whole head.
+#[derive(Clone, Copy)]
+pub struct Options {
+    /// Rejects heads that are merely tolerated otherwise but that servers
+    /// and proxies can disagree about, the root of request smuggling:
+    /// lines not ended by CRLF, stray CR or NUL bytes in header values,
+    /// methods and header names outside the token grammar, and
+    /// Content-Length alongside Transfer-Encoding or repeated with
+    /// different values.
+    pub strict: bool,
+    /// Longest request target, in bytes.
+    pub max_uri_length: usize,
+    /// Longest header line, name and value together, in bytes.
+    pub max_header_line: usize,
+    pub max_headers: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            strict: false,
+            max_uri_length: MAX_HEAD_SIZE,
+            max_header_line: MAX_HEAD_SIZE,
+            max_headers: MAX_HEADERS,
+        }
+    }
+}
+
+/// A parsed request head. Every field is a range into one string holding
+/// the validated head, so a request costs two allocations however many
+/// headers it carries, and accessors hand out borrowed slices.
+pub struct Request {
+    head: String,
+    method: Range<usize>,
+    target: Range<usize>,
+    /// The parts of `target` before and after the first `?`.
+    path: Range<usize>,
+    query: Range<usize>,
+    version: Range<usize>,
+    headers: Vec<(Range<usize>, Range<usize>)>,
+    body_prefix: Vec<u8>,
+}
+
+impl Request {
+    pub fn method(&self) -> &str {
+        &self.head[self.method.clone()]
+    }
+
+    pub fn target(&self) -> &str {
+        &self.head[self.target.clone()]
+    }
+
+    /// The target without its query, still percent-encoded.
+    pub fn path(&self) -> &str {
+        &self.head[self.path.clone()]
+    }
+
+    /// The raw query after the `?`, empty when there is none.
+    pub fn query(&self) -> &str {
+        &self.head[self.query.clone()]
+    }
+
+    /// The decoded query parameters. A bare `name` with no `=` counts as
+    /// present with an empty value, and the first of repeated names wins.
+    pub fn query_map(&self) -> HashMap<String, String> {
+        let mut params = HashMap::new();
+        for pair in self.query().split('&').filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            params
+                .entry(percent_decode(name, true))
+                .or_insert_with(|| percent_decode(value, true));
+        }
+        params
+    }
+
+    pub fn version(&self) -> &str {
+        &self.head[self.version.clone()]
+    }
+
+    /// Header names and values in the order they were received.
+    pub fn headers(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.headers
+            .iter()
+            .map(|(name, value)| (&self.head[name.clone()], &self.head[value.clone()]))
+    }
+
+    /// Returns the first value of the named header, compared case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
+    /// Bytes after the head that arrived in the same reads, the start of
+    /// a body (or of whatever the client sent next).
+    pub fn body_prefix(&self) -> &[u8] {
+        &self.body_prefix
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    /// The connection closed before a single byte arrived.
+    Empty,
+    /// The connection closed part way through the request head.
+    Incomplete,
+    /// The head grew past [`MAX_HEAD_SIZE`] without terminating, or a
+    /// header line or the number of headers went past its limit.
+    TooLarge,
+    /// The request target is longer than its limit, or the request line
+    /// alone ran past [`MAX_HEAD_SIZE`].
+    UriTooLong,
+    Malformed(&'static str),
+    Io(io::Error),
+}
+
+impl From<io::Error> for ParseError {
+    fn from(error: io::Error) -> Self {
+        ParseError::Io(error)
+    }
+}
+
+pub struct Parser {
+    options: Options,
+    buffer: Vec<u8>,
+    /// Offset of the first byte not yet consumed as part of a line.
+    consumed: usize,
+    /// The request being assembled, present once the request line is in.
+    request: Option<Request>,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Parser::new(Options::default())
+    }
+}
+
+impl Parser {
+    pub fn new(options: Options) -> Self {
+        Parser {
+            options,
+            buffer: Vec::with_capacity(1024),
+            consumed: 0,
+            request: None,
+        }
+    }
+
+    /// Appends `bytes` and consumes every complete line. Returns the request
+    /// once the blank line ending the head has been seen; until then returns
+    /// `Ok(None)` and waits for more input.
+    pub fn push(&mut self, bytes: &[u8]) -> Result<Option<Request>, ParseError> {
+        self.buffer.extend_from_slice(bytes);
+
+        while let Some(length) = self.buffer[self.consumed..]
+            .iter()
+            .position(|&byte| byte == b'\n')
+        {
+            let line_end = self.consumed + length;
+            let line = &self.buffer[self.consumed..line_end];
+            let stripped = line.strip_suffix(b"\r");
+            if self.options.strict && stripped.is_none() {
+                return Err(ParseError::Malformed("Line not terminated by CRLF"));
+            }
+            let line = stripped.unwrap_or(line);
+            self.consumed = line_end + 1;
+            if self.consumed > MAX_HEAD_SIZE {
+                return Err(self.too_large());
+            }
+
+            let options = self.options;
+            match &mut self.request {
+                // RFC 9112 asks servers to skip blank lines sent ahead of the request line.
+                None if line.is_empty() => {}
+                None => self.request = Some(parse_request_line(line, &options)?),
+                Some(request) if line.is_empty() => {
+                    if options.strict {
+                        check_framing(request)?;
+                    }
+                    request.body_prefix = self.buffer[self.consumed..].to_vec();
+                    return Ok(self.request.take());
+                }
+                Some(request) => parse_header_line(line, request, &options)?,
+            }
+        }
+
+        // A header line is refused as soon as it is too long, without
+        // waiting for the rest of it.
+        let pending = self.buffer.len() - self.consumed;
+        if self.request.is_some() && pending > self.options.max_header_line {
+            return Err(ParseError::TooLarge);
+        }
+        if self.buffer.len() > MAX_HEAD_SIZE {
+            return Err(self.too_large());
+        }
+        Ok(None)
+    }
+
+    /// The error for a head that ran past [`MAX_HEAD_SIZE`], which is down
+    /// to the target when the request line never ended.
+    fn too_large(&self) -> ParseError {
+        if self.request.is_none() {
+            ParseError::UriTooLong
+        } else {
+            ParseError::TooLarge
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+}
+
+/// Reads from `stream` until a full request head has arrived.
+pub fn read_request(stream: &mut impl Read, options: Options) -> Result<Request, ParseError> {
+    let mut parser = Parser::new(options);
+    let mut chunk = [0; 1024];
+    loop {
+        let bytes_read = stream.read(&mut chunk)?;
+        if bytes_read == 0 {
+            return Err(if parser.is_empty() {
+                ParseError::Empty
+            } else {
+                ParseError::Incomplete
+            });
+        }
+        if let Some(request) = parser.push(&chunk[..bytes_read])? {
+            return Ok(request);
+        }
+    }
+}
+
+/// Reads and throws away the rest of a body `length` bytes long. The
+/// server has no use for request bodies, but closing the connection with
+/// one still unread makes the kernel reset it, which can destroy the
+/// response before the client reads it.
+pub fn discard_body(stream: &mut impl Read, request: &Request, length: u64) -> io::Result<()> {
+    let remaining = length.saturating_sub(request.body_prefix.len() as u64);
+    let discarded = io::copy(&mut stream.take(remaining), &mut io::sink())?;
+    if discarded < remaining {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+fn parse_request_line(line: &[u8], options: &Options) -> Result<Request, ParseError> {
+    let line = std::str::from_utf8(line)
+        .map_err(|_| ParseError::Malformed("Request line is not valid UTF-8"))?;
+    if options.strict {
+        // Exactly one space between the parts (RFC 9112 section 3).
+        let mut parts = line.split(' ');
+        let method = parts.next().unwrap_or_default();
+        if !is_token(method) || parts.clone().count() != 2 || parts.any(str::is_empty) {
+            return Err(ParseError::Malformed("Invalid request line"));
+        }
+    }
+    let mut parts = line.split_whitespace();
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(_), Some(target), Some(_), None) if target.len() > options.max_uri_length => {
+            Err(ParseError::UriTooLong)
+        }
+        (Some(method), Some(target), Some(version), None) if version.starts_with("HTTP/") => {
+            // Sized for the request line and a typical set of headers.
+            let mut head = String::with_capacity(line.len() + 512);
+            let method = append(&mut head, method);
+            let target = append(&mut head, target);
+            let (path, query) = match head[target.clone()].find('?') {
+                Some(mark) => (
+                    target.start..target.start + mark,
+                    target.start + mark + 1..target.end,
+                ),
+                None => (target.clone(), target.end..target.end),
+            };
+            let version = append(&mut head, version);
+            Ok(Request {
+                head,
+                method,
+                target,
+                path,
+                query,
+                version,
+                headers: Vec::with_capacity(16),
+                body_prefix: Vec::new(),
+            })
+        }
+        _ => Err(ParseError::Malformed("Invalid request line")),
+    }
+}
+
+fn parse_header_line(
+    line: &[u8],
+    request: &mut Request,
+    options: &Options,
+) -> Result<(), ParseError> {
+    if line.len() > options.max_header_line || request.headers.len() >= options.max_headers {
+        return Err(ParseError::TooLarge);
+    }
+    // Obsolete line folding is rejected rather than unfolded (RFC 9112 section 5.2).
+    if line.starts_with(b" ") || line.starts_with(b"\t") {
+        return Err(ParseError::Malformed(
+            "Folded header lines are not supported",
+        ));
+    }
+    let colon = line
+        .iter()
+        .position(|&byte| byte == b':')
+        .ok_or(ParseError::Malformed("Header line without a colon"))?;
+    let name = std::str::from_utf8(&line[..colon])
+        .ok()
+        .filter(|name| !name.is_empty() && !name.contains(|c: char| c.is_ascii_whitespace()))
+        .ok_or(ParseError::Malformed("Invalid header name"))?;
+    if options.strict {
+        if !is_token(name) {
+            return Err(ParseError::Malformed("Invalid header name"));
+        }
+        if line[colon + 1..]
+            .iter()
+            .any(|&byte| byte == b'\r' || byte == 0)
+        {
+            return Err(ParseError::Malformed("Invalid character in header value"));
+        }
+    }
+    // Values may legally carry obs-text bytes, which are kept lossily.
+    let value = String::from_utf8_lossy(&line[colon + 1..]);
+    let name = append(&mut request.head, name);
+    let value = append(&mut request.head, value.trim_matches([' ', '\t']));
+    request.headers.push((name, value));
+    Ok(())
+}
+
+/// Refuses heads whose body length a proxy in front could read differently
+/// (RFC 9112 sections 6.1 and 6.3).
+fn check_framing(request: &Request) -> Result<(), ParseError> {
+    let mut lengths = request
+        .headers()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("Content-Length"))
+        .flat_map(|(_, value)| value.split(','))
+        .map(str::trim);
+    if let Some(first) = lengths.next() {
+        if request.header("Transfer-Encoding").is_some() {
+            return Err(ParseError::Malformed(
+                "Both Content-Length and Transfer-Encoding",
+            ));
+        }
+        if first.is_empty()
+            || !first.bytes().all(|byte| byte.is_ascii_digit())
+            || lengths.any(|length| length != first)
+        {
+            return Err(ParseError::Malformed("Invalid Content-Length"));
+        }
+    }
+    Ok(())
+}
+
+/// Whether `text` is an RFC 9110 token, the grammar of methods and header
+/// names.
+fn is_token(text: &str) -> bool {
+    !text.is_empty()
+        && text
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte))
+}
+
+/// Decodes `%XX` escapes, and `+` as a space when `plus_as_space` is set
+/// (`application/x-www-form-urlencoded` query text). Malformed escapes are
+/// kept literally.
+pub fn percent_decode(value: &str, plus_as_space: bool) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'+' if plus_as_space => decoded.push(b' '),
+            // from_str_radix alone would take a sign, decoding `%+f`.
+            b'%' => match value
+                .get(index + 1..index + 3)
+                .filter(|hex| hex.bytes().all(|byte| byte.is_ascii_hexdigit()))
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                Some(byte) => {
+                    decoded.push(byte);
+                    index += 2;
+                }
+                None => decoded.push(b'%'),
+            },
+            byte => decoded.push(byte),
+        }
+        index += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Copies `text` onto the end of `head` and returns where it landed.
+fn append(head: &mut String, text: &str) -> Range<usize> {
+    let start = head.len();
+    head.push_str(text);
+    start..head.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percent_decode_rejects_signs() {
+        assert_eq!(percent_decode("%+f", false), "%+f");
+        assert_eq!(percent_decode("%-1", false), "%-1");
+        assert_eq!(percent_decode("a%20b%2F", false), "a b/");
+        assert_eq!(percent_decode("a+b%2", true), "a b%2");
+    }
+
+    fn parse(head: &[u8], options: Options) -> Result<Option<Request>, ParseError> {
+        Parser::new(options).push(head)
+    }
+
+    fn strict() -> Options {
+        Options {
+            strict: true,
+            ..Options::default()
+        }
+    }
+
+    #[test]
+    fn head_split_across_reads() {
+        let head = "GET /caf\u{e9}?a=1 HTTP/1.1\r\nHost: x\r\nX-Name: \u{e9}t\u{e9}\r\n\r\nbody";
+        let end = head.find("\r\n\r\n").unwrap() + 4;
+        // Every split point in the head, including the middle of each
+        // two-byte character.
+        for split in 0..end {
+            let mut parser = Parser::default();
+            assert!(parser.push(&head.as_bytes()[..split]).unwrap().is_none());
+            let request = parser.push(&head.as_bytes()[split..]).unwrap().unwrap();
+            assert_eq!(request.method(), "GET");
+            assert_eq!(request.path(), "/caf\u{e9}");
+            assert_eq!(request.query(), "a=1");
+            assert_eq!(request.header("x-name"), Some("\u{e9}t\u{e9}"));
+            assert_eq!(request.body_prefix(), b"body");
+        }
+    }
+
+    #[test]
+    fn uri_too_long() {
+        let options = Options {
+            max_uri_length: 16,
+            ..Options::default()
+        };
+        let head = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(16));
+        assert!(matches!(
+            parse(head.as_bytes(), options),
+            Err(ParseError::UriTooLong)
+        ));
+        let head = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(15));
+        assert!(parse(head.as_bytes(), options).unwrap().is_some());
+
+        // A request line that never ends is down to its target too.
+        let line = format!("GET /{}", "a".repeat(MAX_HEAD_SIZE));
+        assert!(matches!(
+            parse(line.as_bytes(), Options::default()),
+            Err(ParseError::UriTooLong)
+        ));
+    }
+
+    #[test]
+    fn header_line_limit() {
+        let options = Options {
+            max_header_line: 32,
+            ..Options::default()
+        };
+        let header = format!("X: {}", "a".repeat(29));
+        let head = format!("GET / HTTP/1.1\r\n{}\r\n\r\n", header);
+        assert!(parse(head.as_bytes(), options).unwrap().is_some());
+        let head = format!("GET / HTTP/1.1\r\n{}a\r\n\r\n", header);
+        assert!(matches!(
+            parse(head.as_bytes(), options),
+            Err(ParseError::TooLarge)
+        ));
+
+        // Refused before the line is finished.
+        let mut parser = Parser::new(options);
+        parser.push(b"GET / HTTP/1.1\r\n").unwrap();
+        assert!(matches!(
+            parser.push(&[b'a'; 33]),
+            Err(ParseError::TooLarge)
+        ));
+    }
+
+    #[test]
+    fn header_count_limit() {
+        let options = Options {
+            max_headers: 3,
+            ..Options::default()
+        };
+        let head = "GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n";
+        assert_eq!(
+            parse(head.as_bytes(), options)
+                .unwrap()
+                .unwrap()
+                .headers()
+                .count(),
+            3
+        );
+        let head = "GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\nD: 4\r\n\r\n";
+        assert!(matches!(
+            parse(head.as_bytes(), options),
+            Err(ParseError::TooLarge)
+        ));
+    }
+
+    #[test]
+    fn strict_rejects_bare_lf() {
+        let head = b"GET / HTTP/1.1\nHost: x\n\n";
+        assert!(parse(head, Options::default()).unwrap().is_some());
+        assert!(matches!(
+            parse(head, strict()),
+            Err(ParseError::Malformed(_))
+        ));
+        let head = b"GET / HTTP/1.1\r\nHost: x\n\r\n";
+        assert!(matches!(
+            parse(head, strict()),
+            Err(ParseError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_obs_fold() {
+        for head in [
+            &b"GET / HTTP/1.1\r\nX-A: 1\r\n 2\r\n\r\n"[..],
+            &b"GET / HTTP/1.1\r\nX-A: 1\r\n\t2\r\n\r\n"[..],
+        ] {
+            assert!(matches!(
+                parse(head, strict()),
+                Err(ParseError::Malformed(_))
+            ));
+            assert!(matches!(
+                parse(head, Options::default()),
+                Err(ParseError::Malformed(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn non_utf8_bytes() {
+        let head = b"GET /\xff HTTP/1.1\r\n\r\n";
+        assert!(matches!(
+            parse(head, Options::default()),
+            Err(ParseError::Malformed(_))
+        ));
+        let head = b"GET / HTTP/1.1\r\nX-\xff: 1\r\n\r\n";
+        assert!(matches!(
+            parse(head, Options::default()),
+            Err(ParseError::Malformed(_))
+        ));
+        // obs-text in a value is allowed and kept lossily.
+        let head = b"GET / HTTP/1.1\r\nX-A: a\xffb\r\n\r\n";
+        let request = parse(head, strict()).unwrap().unwrap();
+        assert_eq!(request.header("X-A"), Some("a\u{fffd}b"));
+    }
+}
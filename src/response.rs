@@ -0,0 +1,78 @@
+/*
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ * More licensing information can be found in the project LICENSE file
+ * Author: Harshit Jain
+ * Email: reach@harsh1998.dev
+ */
+
+//! What a route answers with. Routes decide on a response without touching
+//! the connection; `handle_client` writes it once the route returns.
+
+use crate::{compress, request, Client};
+
+pub enum Response<'a> {
+    /// An error page: the status, its reason phrase and the message.
+    Error(u16, &'static str, &'static str),
+    /// A complete response with extra CRLF-terminated header lines.
+    Full {
+        status: u16,
+        reason: &'static str,
+        content_type: &'static str,
+        headers: String,
+        body: Vec<u8>,
+    },
+    /// A generated 200 body, compressed when the client accepts it.
+    Page {
+        content_type: &'static str,
+        headers: String,
+        body: Vec<u8>,
+    },
+    /// A 304 carrying the validator headers.
+    NotModified(String),
+    /// A body written as it is produced, such as a download or an event
+    /// stream, which answers the client itself.
+    Stream(Box<dyn FnOnce(&mut Client) + 'a>),
+}
+
+impl Response<'_> {
+    pub fn send(
+        self,
+        stream: &mut Client,
+        request: &request::Request,
+        compression: &compress::Settings,
+    ) {
+        match self {
+            Response::Error(status, reason, message) => {
+                crate::send_response(stream, status, reason, message)
+            }
+            Response::Full {
+                status,
+                reason,
+                content_type,
+                headers,
+                body,
+            } => crate::write_response_with_headers(
+                stream,
+                status,
+                reason,
+                content_type,
+                &headers,
+                &body,
+            ),
+            Response::Page {
+                content_type,
+                headers,
+                body,
+            } => crate::write_negotiated_response(
+                stream,
+                request,
+                compression,
+                content_type,
+                &headers,
+                &body,
+            ),
+            Response::NotModified(headers) => crate::send_not_modified(stream, &headers),
+            Response::Stream(write) => write(stream),
+        }
+    }
+}
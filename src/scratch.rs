@@ -0,0 +1,113 @@
+/*
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ * More licensing information can be found in the project LICENSE file
+ * Author: Harshit Jain
+ * Email: reach@harsh1998.dev
+ */
+
+//! The `--temp-dir` scratch directory, where files being produced (such as
+//! mirrored downloads in flight) are written before they are moved into the
+//! served tree.
+
+use std::fs::{self, File};
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Space left free on the scratch filesystem on top of whatever a new file
+/// is expected to need, so one large download cannot fill the disk.
+const RESERVE: u64 = 64 * 1024 * 1024;
+
+pub struct ScratchDir {
+    path: PathBuf,
+    counter: AtomicU64,
+}
+
+impl ScratchDir {
+    /// Makes sure the directory exists and files can be written there, so a
+    /// bad setting fails at startup instead of on first use.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let path = path.canonicalize()?;
+        if !path.is_dir() {
+            return Err(io::Error::new(ErrorKind::NotADirectory, "not a directory"));
+        }
+        let probe = path.join(format!(".hdl_sv-probe-{}", std::process::id()));
+        File::create(&probe)?;
+        fs::remove_file(&probe)?;
+        Ok(ScratchDir {
+            path,
+            counter: AtomicU64::new(0),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Bytes available to this process on the scratch filesystem, where the
+    /// platform can tell.
+    pub fn available_space(&self) -> Option<u64> {
+        available_space(&self.path)
+    }
+
+    /// Creates a fresh file for a future `target`. Fails with `StorageFull`
+    /// when `needed` bytes would eat into the reserve.
+    pub fn create(&self, target: &Path, needed: Option<u64>) -> io::Result<(File, PathBuf)> {
+        if let Some(available) = self.available_space() {
+            if needed.unwrap_or(0).saturating_add(RESERVE) > available {
+                return Err(io::Error::new(
+                    ErrorKind::StorageFull,
+                    format!("not enough free space in {:?}", self.path),
+                ));
+            }
+        }
+        let path = self.path.join(format!(
+            "{}.{}-{}.part",
+            target.file_name().unwrap_or_default().to_string_lossy(),
+            std::process::id(),
+            self.counter.fetch_add(1, Ordering::Relaxed)
+        ));
+        Ok((File::create(&path)?, path))
+    }
+}
+
+/// Moves a finished scratch file to `target`. When the scratch directory is
+/// on another filesystem the file is copied beside the target first, so
+/// the target still only ever appears complete.
+pub fn persist(temp_path: &Path, target: &Path) -> io::Result<()> {
+    match fs::rename(temp_path, target) {
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+            let staged = target.with_file_name(format!(
+                ".{}",
+                temp_path.file_name().unwrap_or_default().to_string_lossy()
+            ));
+            // A copy gets the current time, so the original's is carried over.
+            let copied = fs::copy(temp_path, &staged)
+                .and_then(|_| fs::metadata(temp_path)?.modified())
+                .and_then(|modified| {
+                    File::options()
+                        .write(true)
+                        .open(&staged)?
+                        .set_modified(modified)
+                })
+                .and_then(|_| fs::rename(&staged, target));
+            if copied.is_err() {
+                let _ = fs::remove_file(&staged);
+            }
+            let _ = fs::remove_file(temp_path);
+            copied
+        }
+        result => result,
+    }
+}
+
+#[cfg(unix)]
+fn available_space(path: &Path) -> Option<u64> {
+    let stats = rustix::fs::statvfs(path).ok()?;
+    Some(stats.f_bavail.saturating_mul(stats.f_frsize))
+}
+
+#[cfg(not(unix))]
+fn available_space(_path: &Path) -> Option<u64> {
+    None
+}
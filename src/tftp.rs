@@ -0,0 +1,506 @@
+/*
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ * More licensing information can be found in the project LICENSE file
+ * Author: Harshit Jain
+ * Email: reach@harsh1998.dev
+ */
+
+//! Read-only TFTP listener (RFC 1350) with the blksize (RFC 2348) and
+//! tsize (RFC 2349) options, serving the same directory as the HTTP side.
+//! Requests go through the same connection ceiling, bans, rate limit and
+//! country rules as HTTP ones, and are served by a fixed pool of workers.
+
+use crate::access::{AccessRules, Extensions};
+use crate::admission::{Admission, Connection};
+use crate::ignore_rules::IgnoreRules;
+use std::fs::File;
+use std::io::{self, Read};
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const OPCODE_RRQ: u16 = 1;
+const OPCODE_WRQ: u16 = 2;
+const OPCODE_DATA: u16 = 3;
+const OPCODE_ACK: u16 = 4;
+const OPCODE_ERROR: u16 = 5;
+const OPCODE_OACK: u16 = 6;
+
+const ERROR_NOT_FOUND: u16 = 1;
+const ERROR_ACCESS_VIOLATION: u16 = 2;
+const ERROR_ILLEGAL_OPERATION: u16 = 4;
+
+const DEFAULT_BLOCK_SIZE: usize = 512;
+const MIN_BLOCK_SIZE: usize = 8;
+/// The largest block that fits an Ethernet frame without fragmenting
+/// (RFC 2348), which also bounds what one forged request can draw.
+const MAX_BLOCK_SIZE: usize = 1468;
+const RETRANSMIT_TIMEOUT: Duration = Duration::from_secs(2);
+const MAX_RETRIES: u32 = 5;
+/// Transfers served at once.
+const WORKERS: usize = 16;
+/// Requests waiting for a worker. Past this they are dropped, and the
+/// client's own retransmission asks again.
+const QUEUE_LENGTH: usize = 64;
+
+struct ReadRequest {
+    filename: String,
+    mode: String,
+    options: Vec<(String, String)>,
+}
+
+pub fn serve(
+    bind_address: String,
+    root: PathBuf,
+    extensions: Arc<Extensions>,
+    ignore_rules: Arc<IgnoreRules>,
+    access_rules: Arc<AccessRules>,
+    admission: Arc<Admission>,
+) {
+    let socket = match UdpSocket::bind(&bind_address) {
+        Ok(socket) => socket,
+        Err(e) => {
+            eprintln!("Error binding TFTP listener on {}: {}", bind_address, e);
+            return;
+        }
+    };
+    let local_ip = socket.local_addr().unwrap().ip();
+    println!("TFTP listening on {}", bind_address);
+
+    let (sender, receiver) = mpsc::sync_channel::<(Vec<u8>, SocketAddr, Connection)>(QUEUE_LENGTH);
+    let receiver = Arc::new(Mutex::new(receiver));
+    for _ in 0..WORKERS {
+        let receiver = Arc::clone(&receiver);
+        let root = root.clone();
+        let extensions = Arc::clone(&extensions);
+        let ignore_rules = Arc::clone(&ignore_rules);
+        let access_rules = Arc::clone(&access_rules);
+        thread::spawn(move || loop {
+            let received = receiver.lock().unwrap().recv();
+            let Ok((packet, peer, _connection)) = received else {
+                return;
+            };
+            handle_request(
+                &packet,
+                peer,
+                local_ip,
+                &root,
+                &extensions,
+                &ignore_rules,
+                &access_rules,
+            );
+        });
+    }
+
+    // Large enough for any request, however many options it carries.
+    let mut buffer = vec![0; 65536];
+    loop {
+        let (length, peer) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e) => {
+                eprintln!("Error receiving TFTP request: {}", e);
+                continue;
+            }
+        };
+        // The source address of a datagram may be forged. Refused requests
+        // are dropped without an answer, and an admitted one draws a single
+        // packet, never retransmitted, until the peer acknowledges it, so a
+        // spoofed request is reflected at most once and barely amplified.
+        if !admission.is_country_allowed(peer.ip()) {
+            continue;
+        }
+        let Ok(connection) = admission.admit(Some(peer.ip())) else {
+            continue;
+        };
+        let _ = sender.try_send((buffer[..length].to_vec(), peer, connection));
+    }
+}
+
+fn handle_request(
+    packet: &[u8],
+    peer: SocketAddr,
+    local_ip: IpAddr,
+    root: &Path,
+    extensions: &Extensions,
+    ignore_rules: &IgnoreRules,
+    access_rules: &AccessRules,
+) {
+    // Every transfer gets its own socket so the reply comes from a fresh
+    // transfer identifier, as RFC 1350 requires.
+    let socket = match UdpSocket::bind((local_ip, 0)).and_then(|socket| {
+        socket.connect(peer)?;
+        socket.set_read_timeout(Some(RETRANSMIT_TIMEOUT))?;
+        Ok(socket)
+    }) {
+        Ok(socket) => socket,
+        Err(e) => {
+            eprintln!("Error opening TFTP transfer socket for {}: {}", peer, e);
+            return;
+        }
+    };
+
+    let request = match opcode(packet) {
+        Some(OPCODE_RRQ) => match parse_read_request(&packet[2..]) {
+            Some(request) => request,
+            None => {
+                send_error(&socket, ERROR_ILLEGAL_OPERATION, "Malformed request");
+                return;
+            }
+        },
+        Some(OPCODE_WRQ) => {
+            send_error(&socket, ERROR_ACCESS_VIOLATION, "Server is read-only");
+            return;
+        }
+        _ => {
+            send_error(&socket, ERROR_ILLEGAL_OPERATION, "Illegal TFTP operation");
+            return;
+        }
+    };
+
+    // netascii is served byte-for-byte; every client we care about asks for octet.
+    let mode = request.mode.to_ascii_lowercase();
+    if mode != "octet" && mode != "netascii" {
+        send_error(&socket, ERROR_ILLEGAL_OPERATION, "Unsupported transfer mode");
+        return;
+    }
+
+    let path = match resolve_path(
+        root,
+        &request.filename,
+        extensions,
+        ignore_rules,
+        access_rules,
+    ) {
+        Ok(path) => path,
+        Err((code, message)) => {
+            send_error(&socket, code, message);
+            return;
+        }
+    };
+    let mut file = match File::open(&path) {
+        Ok(file) => file,
+        Err(_) => {
+            send_error(&socket, ERROR_NOT_FOUND, "File not found");
+            return;
+        }
+    };
+    let file_size = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+
+    let mut block_size = DEFAULT_BLOCK_SIZE;
+    let mut acknowledged = Vec::new();
+    for (name, value) in &request.options {
+        if name.eq_ignore_ascii_case("blksize") {
+            if let Ok(requested) = value.parse::<usize>() {
+                if requested >= MIN_BLOCK_SIZE {
+                    block_size = requested.min(MAX_BLOCK_SIZE);
+                    acknowledged.push(("blksize", block_size.to_string()));
+                }
+            }
+        } else if name.eq_ignore_ascii_case("tsize") {
+            acknowledged.push(("tsize", file_size.to_string()));
+        }
+    }
+
+    if !acknowledged.is_empty() {
+        let mut oack = OPCODE_OACK.to_be_bytes().to_vec();
+        for (name, value) in &acknowledged {
+            oack.extend_from_slice(name.as_bytes());
+            oack.push(0);
+            oack.extend_from_slice(value.as_bytes());
+            oack.push(0);
+        }
+        if let Err(e) = send_and_await_ack(&socket, &oack, 0, 1) {
+            eprintln!("TFTP option negotiation with {} failed: {}", peer, e);
+            return;
+        }
+    }
+
+    // An acknowledged OACK already shows the peer is really there.
+    let verified = !acknowledged.is_empty();
+    if let Err(e) = send_file(&socket, &mut file, block_size, verified) {
+        eprintln!(
+            "Error sending {} over TFTP to {}: {}",
+            path.display(),
+            peer,
+            e
+        );
+    }
+}
+
+/// Until `verified`, the first block is sent only once: a client that is
+/// really there asks again by retransmitting its request.
+fn send_file(
+    socket: &UdpSocket,
+    file: &mut File,
+    block_size: usize,
+    mut verified: bool,
+) -> io::Result<()> {
+    let mut packet = vec![0; block_size + 4];
+    let mut block: u16 = 1;
+    loop {
+        let mut filled = 0;
+        while filled < block_size {
+            let bytes_read = file.read(&mut packet[4 + filled..])?;
+            if bytes_read == 0 {
+                break;
+            }
+            filled += bytes_read;
+        }
+        packet[..2].copy_from_slice(&OPCODE_DATA.to_be_bytes());
+        packet[2..4].copy_from_slice(&block.to_be_bytes());
+        let attempts = if verified { MAX_RETRIES } else { 1 };
+        send_and_await_ack(socket, &packet[..4 + filled], block, attempts)?;
+        verified = true;
+
+        // A short block tells the client the transfer is complete.
+        if filled < block_size {
+            return Ok(());
+        }
+        block = block.wrapping_add(1);
+    }
+}
+
+fn send_and_await_ack(
+    socket: &UdpSocket,
+    packet: &[u8],
+    block: u16,
+    attempts: u32,
+) -> io::Result<()> {
+    let mut buffer = [0; DEFAULT_BLOCK_SIZE + 4];
+    for _ in 0..attempts {
+        socket.send(packet)?;
+        loop {
+            let length = match socket.recv(&mut buffer) {
+                Ok(length) => length,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    break;
+                }
+                Err(e) => return Err(e),
+            };
+            match opcode(&buffer[..length]) {
+                Some(OPCODE_ACK) if length >= 4 && buffer[2..4] == block.to_be_bytes() => {
+                    return Ok(());
+                }
+                Some(OPCODE_ERROR) => {
+                    return Err(io::Error::other("transfer aborted by client"));
+                }
+                // Duplicate ACKs for earlier blocks are ignored rather than
+                // answered, which avoids the Sorcerer's Apprentice bug.
+                _ => continue,
+            }
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::TimedOut,
+        "no acknowledgement from client",
+    ))
+}
+
+fn send_error(socket: &UdpSocket, code: u16, message: &str) {
+    let mut packet = OPCODE_ERROR.to_be_bytes().to_vec();
+    packet.extend_from_slice(&code.to_be_bytes());
+    packet.extend_from_slice(message.as_bytes());
+    packet.push(0);
+    let _ = socket.send(&packet);
+}
+
+fn opcode(packet: &[u8]) -> Option<u16> {
+    packet
+        .get(..2)
+        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn parse_read_request(payload: &[u8]) -> Option<ReadRequest> {
+    let mut fields = payload
+        .strip_suffix(&[0])?
+        .split(|&byte| byte == 0)
+        .map(|field| String::from_utf8_lossy(field).into_owned());
+    let filename = fields.next().filter(|name| !name.is_empty())?;
+    let mode = fields.next()?;
+    let mut options = Vec::new();
+    while let (Some(name), Some(value)) = (fields.next(), fields.next()) {
+        options.push((name, value));
+    }
+    Some(ReadRequest {
+        filename,
+        mode,
+        options,
+    })
+}
+
+fn resolve_path(
+    root: &Path,
+    filename: &str,
+    extensions: &Extensions,
+    ignore_rules: &IgnoreRules,
+    access_rules: &AccessRules,
+) -> Result<PathBuf, (u16, &'static str)> {
+    // Every escape gets the same answer, before anything outside the root
+    // is looked at, so a client cannot learn which files exist there.
+    let requested = join_inside(root, filename).ok_or((ERROR_ACCESS_VIOLATION, "Access denied"))?;
+    if ignore_rules.is_ignored(&requested) {
+        return Err((ERROR_NOT_FOUND, "File not found"));
+    }
+    let path = requested
+        .canonicalize()
+        .map_err(|_| (ERROR_NOT_FOUND, "File not found"))?;
+    if !path.starts_with(root) {
+        return Err((ERROR_ACCESS_VIOLATION, "Access denied"));
+    }
+    if !path.is_file() || ignore_rules.is_ignored(&path) {
+        return Err((ERROR_NOT_FOUND, "File not found"));
+    }
+    let access = access_rules.resolve_path(&path);
+    if !access.can_download(&path, extensions) {
+        return Err((
+            ERROR_ACCESS_VIOLATION,
+            "Only allowed files can be downloaded",
+        ));
+    }
+    // TFTP has no way to present credentials.
+    if access.auth.is_some() {
+        return Err((ERROR_ACCESS_VIOLATION, "Access denied"));
+    }
+    Ok(path)
+}
+
+/// Joins `filename` onto `root` lexically, or `None` when a `..` would
+/// climb above the root.
+fn join_inside(root: &Path, filename: &str) -> Option<PathBuf> {
+    let mut path = root.to_path_buf();
+    let mut depth = 0;
+    for component in Path::new(filename).components() {
+        match component {
+            Component::Normal(name) => {
+                path.push(name);
+                depth += 1;
+            }
+            Component::ParentDir if depth > 0 => {
+                path.pop();
+                depth -= 1;
+            }
+            Component::RootDir | Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    Some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ignore_rules::HiddenFiles;
+    use std::fs;
+
+    /// A served root inside a scratch directory, with `outside.txt` beside
+    /// it.
+    struct Fixture {
+        _scratch: tempfile::TempDir,
+        root: PathBuf,
+        extensions: Extensions,
+        ignore_rules: IgnoreRules,
+        access_rules: AccessRules,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            let scratch = tempfile::tempdir().unwrap();
+            let base = scratch.path().canonicalize().unwrap();
+            let root = base.join("root");
+            fs::create_dir_all(root.join("pub")).unwrap();
+            fs::create_dir_all(root.join("staff")).unwrap();
+            fs::write(base.join("outside.txt"), "").unwrap();
+            fs::write(root.join("pub/boot.txt"), "").unwrap();
+            fs::write(root.join("pub/boot.key"), "").unwrap();
+            fs::write(root.join("pub/notes.log"), "").unwrap();
+            fs::write(root.join(".hdlignore"), "*.log\n").unwrap();
+            fs::write(root.join("staff/plan.txt"), "").unwrap();
+            let account = format!("user = alice:{}\n", bcrypt::hash("pw", 4).unwrap());
+            fs::write(root.join("staff/.hdl_access"), account).unwrap();
+            Fixture {
+                _scratch: scratch,
+                extensions: Extensions {
+                    allowed: vec![String::from("txt"), String::from("log")],
+                    denied: vec![String::from("key")],
+                },
+                ignore_rules: IgnoreRules::new(root.clone(), false, HiddenFiles::Deny),
+                access_rules: AccessRules::new(root.clone()),
+                root,
+            }
+        }
+
+        fn resolve(&self, filename: &str) -> Result<PathBuf, u16> {
+            resolve_path(
+                &self.root,
+                filename,
+                &self.extensions,
+                &self.ignore_rules,
+                &self.access_rules,
+            )
+            .map_err(|(code, _)| code)
+        }
+    }
+
+    #[test]
+    fn serves_allowed_files() {
+        let fixture = Fixture::new();
+        let boot = fixture.root.join("pub/boot.txt");
+        assert_eq!(fixture.resolve("pub/boot.txt"), Ok(boot.clone()));
+        assert_eq!(fixture.resolve("/pub/boot.txt"), Ok(boot.clone()));
+        assert_eq!(fixture.resolve("pub/../pub/boot.txt"), Ok(boot));
+    }
+
+    #[test]
+    fn stays_inside_root() {
+        let fixture = Fixture::new();
+        assert_eq!(
+            fixture.resolve("../outside.txt"),
+            Err(ERROR_ACCESS_VIOLATION)
+        );
+        assert_eq!(
+            fixture.resolve("pub/../../outside.txt"),
+            Err(ERROR_ACCESS_VIOLATION)
+        );
+        // Whether the file exists outside the root makes no difference.
+        assert_eq!(
+            fixture.resolve("../missing.txt"),
+            Err(ERROR_ACCESS_VIOLATION)
+        );
+        assert_eq!(
+            fixture.resolve("/../root/pub/boot.txt"),
+            Err(ERROR_ACCESS_VIOLATION)
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_out_of_root_are_refused() {
+        let fixture = Fixture::new();
+        let outside = fixture.root.parent().unwrap().join("outside.txt");
+        std::os::unix::fs::symlink(outside, fixture.root.join("pub/link.txt")).unwrap();
+        assert_eq!(fixture.resolve("pub/link.txt"), Err(ERROR_ACCESS_VIOLATION));
+    }
+
+    #[test]
+    fn honours_ignore_and_access_rules() {
+        let fixture = Fixture::new();
+        assert_eq!(fixture.resolve("pub/notes.log"), Err(ERROR_NOT_FOUND));
+        assert_eq!(fixture.resolve("staff/.hdl_access"), Err(ERROR_NOT_FOUND));
+        assert_eq!(fixture.resolve(".hdlignore"), Err(ERROR_NOT_FOUND));
+        assert_eq!(fixture.resolve("pub/boot.key"), Err(ERROR_ACCESS_VIOLATION));
+        // TFTP cannot carry credentials, so protected files are never sent.
+        assert_eq!(
+            fixture.resolve("staff/plan.txt"),
+            Err(ERROR_ACCESS_VIOLATION)
+        );
+        assert_eq!(fixture.resolve("pub"), Err(ERROR_NOT_FOUND));
+        assert_eq!(fixture.resolve("pub/missing.txt"), Err(ERROR_NOT_FOUND));
+    }
+}
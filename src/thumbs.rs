@@ -0,0 +1,167 @@
+/*
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ * More licensing information can be found in the project LICENSE file
+ * Author: Harshit Jain
+ * Email: reach@harsh1998.dev
+ */
+
+//! Small JPEG previews of images (`--thumbnails`), served at `/_thumb/` and
+//! shown beside image files in listings so photo folders can be browsed
+//! without opening every picture. Decoding needs the `thumbnails` feature.
+
+use crate::response::Response;
+use crate::{
+    file_etag, is_not_modified, modified_secs, resolve_request_path, ServerConfig, Target,
+    Validator,
+};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Images larger than this are not decoded; a thumbnail would cost more
+/// memory and time than it is worth.
+pub const MAX_SOURCE_SIZE: u64 = 50 * 1024 * 1024;
+
+/// Thumbnails kept in memory. Past this the cache starts over rather than
+/// tracking which entries were used last.
+const MAX_CACHED: usize = 4096;
+
+const EXTENSIONS: [&str; 5] = ["jpg", "jpeg", "png", "gif", "webp"];
+
+/// Whether `path` looks like an image a thumbnail can be made of.
+pub fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(std::ffi::OsStr::to_str)
+        .is_some_and(|extension| {
+            EXTENSIONS
+                .iter()
+                .any(|image| image.eq_ignore_ascii_case(extension))
+        })
+}
+
+/// `/_thumb/<image>`, with --thumbnails: the preview of an image the client
+/// may download. Without it the path is left to be served like any other.
+pub fn route<'a>(target: &Target, config: &ServerConfig) -> Option<Response<'a>> {
+    let thumbnails = config.thumbnails.as_ref()?;
+    let path = resolve_request_path(
+        target.root,
+        // Route prefixes have no escapes, so the decoded path starts with
+        // them too.
+        &target.path["/_thumb/".len()..],
+        config.normalization,
+    );
+    let access = target.access_rules.resolve_path(&path);
+    if !path.is_file() || !is_image(&path) || target.ignore_rules.is_ignored(&path) {
+        return Some(Response::Error(404, "Not Found", "File not found"));
+    }
+    if let Err(response) = target.authorize(&access, config) {
+        return Some(response);
+    }
+    if !access.can_download(&path, target.extensions) {
+        return Some(Response::Error(
+            403,
+            "Forbidden",
+            "Only allowed files can be downloaded",
+        ));
+    }
+    let Ok(metadata) = fs::metadata(&path) else {
+        return Some(Response::Error(404, "Not Found", "File not found"));
+    };
+    // A thumbnail changes exactly when its image does.
+    let validator = Validator {
+        etag: file_etag(metadata.len(), metadata.modified().unwrap_or(UNIX_EPOCH)),
+        last_modified: modified_secs(&metadata),
+    };
+    if is_not_modified(target.request, &validator) {
+        return Some(Response::NotModified(validator.headers()));
+    }
+    Some(match thumbnails.thumbnail(&path) {
+        Ok(data) => Response::Full {
+            status: 200,
+            reason: "OK",
+            content_type: "image/jpeg",
+            headers: validator.headers(),
+            body: data.to_vec(),
+        },
+        Err(e) => {
+            eprintln!("Error generating thumbnail for {:?}: {}", path, e);
+            Response::Error(404, "Not Found", "File not found")
+        }
+    })
+}
+
+#[derive(Default)]
+pub struct Cache {
+    entries: Mutex<HashMap<PathBuf, Entry>>,
+}
+
+struct Entry {
+    length: u64,
+    modified: SystemTime,
+    data: Arc<Vec<u8>>,
+}
+
+impl Cache {
+    pub fn thumbnail(&self, path: &Path) -> io::Result<Arc<Vec<u8>>> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata.modified()?;
+        if let Some(entry) = self.entries.lock().unwrap().get(path) {
+            if entry.length == metadata.len() && entry.modified == modified {
+                return Ok(Arc::clone(&entry.data));
+            }
+        }
+        if metadata.len() > MAX_SOURCE_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "image is too large for a thumbnail",
+            ));
+        }
+
+        // Decoding takes a while, so it is done without holding the lock.
+        let data = Arc::new(generate(path)?);
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_CACHED {
+            entries.clear();
+        }
+        entries.insert(
+            path.to_path_buf(),
+            Entry {
+                length: metadata.len(),
+                modified,
+                data: Arc::clone(&data),
+            },
+        );
+        Ok(data)
+    }
+}
+
+#[cfg(feature = "thumbnails")]
+fn generate(path: &Path) -> io::Result<Vec<u8>> {
+    /// The longest side of a thumbnail, in pixels.
+    const SIZE: u32 = 160;
+
+    let mut reader = image::ImageReader::open(path)?.with_guessed_format()?;
+    // A small file can still claim enormous dimensions.
+    let mut limits = image::Limits::default();
+    limits.max_alloc = Some(256 * 1024 * 1024);
+    reader.limits(limits);
+    let image = reader.decode().map_err(io::Error::other)?;
+    let mut data = Vec::new();
+    image
+        .thumbnail(SIZE, SIZE)
+        .into_rgb8()
+        .write_to(&mut io::Cursor::new(&mut data), image::ImageFormat::Jpeg)
+        .map_err(io::Error::other)?;
+    Ok(data)
+}
+
+#[cfg(not(feature = "thumbnails"))]
+fn generate(_path: &Path) -> io::Result<Vec<u8>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "built without the thumbnails feature",
+    ))
+}
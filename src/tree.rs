@@ -0,0 +1,184 @@
+/*
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ * More licensing information can be found in the project LICENSE file
+ * Author: Harshit Jain
+ * Email: reach@harsh1998.dev
+ */
+
+//! `/_tree`: a directory and its descendants as nested JSON, bounded in
+//! depth and entry count, under the same ignore and access rules as a
+//! listing.
+
+use crate::access::{Access, AccessRules};
+use crate::ignore_rules::IgnoreRules;
+use crate::response::Response;
+use crate::{
+    directory_validator, encode_path, escape_json, is_not_modified, modified_secs,
+    resolve_request_path, ServerConfig, Target,
+};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const MAX_TREE_DEPTH: usize = 32;
+pub const MAX_TREE_ENTRIES: usize = 10_000;
+
+/// `/_tree/<dir>?depth=N`: the directory and its descendants, `depth`
+/// levels down (1 by default), as JSON.
+pub fn route<'a>(target: &Target, config: &ServerConfig) -> Response<'a> {
+    let depth = match target
+        .request
+        .query_map()
+        .get("depth")
+        .map(|depth| depth.parse::<usize>())
+    {
+        None => 1,
+        Some(Ok(depth)) => depth.min(MAX_TREE_DEPTH),
+        Some(Err(_)) => return Response::Error(400, "Bad Request", "Invalid depth"),
+    };
+    let directory = match resolve_request_path(
+        target.root,
+        // Route prefixes have no escapes, so the decoded path starts with
+        // them too.
+        &target.path["/_tree".len()..],
+        config.normalization,
+    )
+    .canonicalize()
+    {
+        Ok(directory)
+            if directory.starts_with(target.root)
+                && directory.is_dir()
+                && !target.ignore_rules.is_ignored(&directory) =>
+        {
+            directory
+        }
+        _ => return Response::Error(404, "Not Found", "Directory not found"),
+    };
+    let access = target.access_rules.resolve(&directory);
+    if access.denied {
+        return Response::Error(404, "Not Found", "Directory not found");
+    }
+    if let Err(response) = target.authorize(&access, config) {
+        return response;
+    }
+    let validator = directory_validator(
+        &directory,
+        &access,
+        depth,
+        target.request.query(),
+        "application/json",
+        target.ignore_rules,
+        target.access_rules,
+    );
+    if is_not_modified(target.request, &validator) {
+        return Response::NotModified(validator.headers());
+    }
+    let json = generate_tree_json(
+        target.root,
+        &directory,
+        &access,
+        depth,
+        target.ignore_rules,
+        target.access_rules,
+    );
+    Response::Page {
+        content_type: "application/json",
+        headers: validator.headers(),
+        body: json.into_bytes(),
+    }
+}
+
+/// Walk state shared by every level of a `/_tree` response.
+struct TreeWalk<'a> {
+    root: &'a Path,
+    ignore_rules: &'a IgnoreRules,
+    access_rules: &'a AccessRules,
+    /// The realm of the directory the walk starts from.
+    realm: Option<&'a str>,
+    remaining: usize,
+    truncated: bool,
+}
+
+fn generate_tree_json(
+    root: &Path,
+    directory: &Path,
+    access: &Access,
+    depth: usize,
+    ignore_rules: &IgnoreRules,
+    access_rules: &AccessRules,
+) -> String {
+    let mut walk = TreeWalk {
+        root,
+        ignore_rules,
+        access_rules,
+        realm: access.realm(),
+        remaining: MAX_TREE_ENTRIES,
+        truncated: false,
+    };
+    let tree = walk.node(directory, access, depth);
+    format!(
+        r#"{{"depth":{},"truncated":{},"tree":{}}}"#,
+        depth, walk.truncated, tree
+    )
+}
+
+impl TreeWalk<'_> {
+    /// Renders one entry; directories list their children while `depth`
+    /// allows it and omit the `children` key once it runs out. `access`
+    /// holds the rules for `path` when it is a directory.
+    fn node(&mut self, path: &Path, access: &Access, depth: usize) -> String {
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(_) => return String::from("null"),
+        };
+        let relative_path = path.strip_prefix(self.root).unwrap_or(path);
+        let name = path
+            .file_name()
+            .filter(|_| path != self.root)
+            .unwrap_or_default()
+            .to_string_lossy();
+        let href = format!("/{}", encode_path(relative_path));
+
+        if !metadata.is_dir() {
+            return format!(
+                r#"{{"name":"{}","type":"file","path":"{}","size":{},"modified":{}}}"#,
+                escape_json(&name),
+                escape_json(&href),
+                metadata.len(),
+                modified_secs(&metadata)
+            );
+        }
+
+        let mut node = format!(
+            r#"{{"name":"{}","type":"directory","path":"{}","modified":{}"#,
+            escape_json(&name),
+            escape_json(&href),
+            modified_secs(&metadata)
+        );
+        if depth > 0 && access.can_list(self.realm) {
+            let mut entries: Vec<PathBuf> = fs::read_dir(path)
+                .map(|entries| entries.flatten().map(|entry| entry.path()).collect())
+                .unwrap_or_default();
+            entries.retain(|entry| !self.ignore_rules.is_unlisted(entry));
+            entries.sort();
+
+            let mut children = Vec::new();
+            for entry in entries {
+                if self.remaining == 0 {
+                    self.truncated = true;
+                    break;
+                }
+                self.remaining -= 1;
+                // A child at depth 0 is not listed, so its rules are never read.
+                let child = if depth > 1 && entry.is_dir() {
+                    let access = self.access_rules.descend(access, &entry);
+                    self.node(&entry, &access, depth - 1)
+                } else {
+                    self.node(&entry, access, depth - 1)
+                };
+                children.push(child);
+            }
+            node += &format!(r#","children":[{}]"#, children.join(","));
+        }
+        node + "}"
+    }
+}
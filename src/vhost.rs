@@ -0,0 +1,140 @@
+/*
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ * More licensing information can be found in the project LICENSE file
+ * Author: Harshit Jain
+ * Email: reach@harsh1998.dev
+ */
+
+//! Host header checks (`--allowed-hosts`) and name-based virtual hosts
+//! (`--vhost name=dir`), each serving its own directory with its own
+//! ignore and access files.
+
+use crate::access::AccessRules;
+use crate::ignore_rules::{HiddenFiles, IgnoreRules};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// A directory served for one Host name.
+pub struct Site {
+    pub name: String,
+    pub root: String,
+    pub ignore_rules: Arc<IgnoreRules>,
+    pub access_rules: Arc<AccessRules>,
+}
+
+impl Site {
+    pub fn open(
+        name: &str,
+        root: &std::path::Path,
+        use_gitignore: bool,
+        hidden_files: HiddenFiles,
+    ) -> std::io::Result<Self> {
+        let root = root.canonicalize()?;
+        Ok(Site {
+            name: host_name(name),
+            root: root.to_string_lossy().to_string(),
+            ignore_rules: Arc::new(IgnoreRules::new(root.clone(), use_gitignore, hidden_files)),
+            access_rules: Arc::new(AccessRules::new(root)),
+        })
+    }
+}
+
+pub struct Hosts {
+    /// Host names requests may carry; empty accepts any.
+    pub allowed: Vec<String>,
+    pub sites: Vec<Site>,
+}
+
+impl Hosts {
+    /// Whether a request naming `host` should be served at all. Virtual
+    /// host names are always allowed.
+    pub fn is_allowed(&self, host: &str) -> bool {
+        self.allowed.is_empty()
+            || self.allowed.iter().any(|allowed| allowed == host)
+            || self.site(host).is_some()
+    }
+
+    pub fn site(&self, host: &str) -> Option<&Site> {
+        self.sites.iter().find(|site| site.name == host)
+    }
+}
+
+/// Parses a `--vhost` value of the form `name=directory`.
+pub fn parse_spec(spec: &str) -> Result<(String, PathBuf), String> {
+    match spec.split_once('=') {
+        Some((name, directory)) if !name.trim().is_empty() && !directory.is_empty() => {
+            Ok((name.trim().to_string(), PathBuf::from(directory)))
+        }
+        _ => Err(String::from("expected name=directory")),
+    }
+}
+
+/// Reduces a Host header value to the bare, lowercase name it compares
+/// by: without the port, or a trailing dot from a fully qualified name.
+pub fn host_name(host: &str) -> String {
+    let host = host.trim();
+    let name = if host.starts_with('[') {
+        // An IPv6 literal keeps its brackets; only a port follows them.
+        host.split_once(']')
+            .map_or(host, |(address, _)| &host[..address.len() + 1])
+    } else {
+        host.split(':').next().unwrap_or_default()
+    };
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_names_compare_bare() {
+        assert_eq!(host_name("Example.COM:8080"), "example.com");
+        assert_eq!(host_name(" example.com. "), "example.com");
+        assert_eq!(host_name("[::1]:8080"), "[::1]");
+        assert_eq!(host_name("[::1]"), "[::1]");
+        assert_eq!(host_name("192.0.2.1:80"), "192.0.2.1");
+        assert_eq!(host_name(""), "");
+    }
+
+    #[test]
+    fn only_listed_hosts_are_allowed() {
+        let root = tempfile::tempdir().unwrap();
+        let site = Site::open("Files.Example.com.", root.path(), false, HiddenFiles::Deny);
+        let hosts = Hosts {
+            allowed: vec![String::from("example.com")],
+            sites: vec![site.unwrap()],
+        };
+        assert!(hosts.is_allowed("example.com"));
+        // Virtual hosts need no separate listing.
+        assert!(hosts.is_allowed("files.example.com"));
+        assert!(hosts.site("files.example.com").is_some());
+        assert!(!hosts.is_allowed("evil.example"));
+        assert!(!hosts.is_allowed(""));
+
+        let open = Hosts {
+            allowed: Vec::new(),
+            sites: Vec::new(),
+        };
+        assert!(open.is_allowed("evil.example"));
+        assert!(open.site("evil.example").is_none());
+    }
+
+    #[test]
+    fn specs_parse() {
+        assert_eq!(
+            parse_spec(" files.example.com =/srv/files"),
+            Ok((
+                String::from("files.example.com"),
+                PathBuf::from("/srv/files")
+            ))
+        );
+        assert_eq!(
+            parse_spec("a=b=c"),
+            Ok((String::from("a"), PathBuf::from("b=c")))
+        );
+        for spec in ["files.example.com", "=/srv", "name=", " =/srv"] {
+            assert!(parse_spec(spec).is_err(), "{}", spec);
+        }
+    }
+}
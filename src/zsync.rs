@@ -0,0 +1,203 @@
+/*
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ * More licensing information can be found in the project LICENSE file
+ * Author: Harshit Jain
+ * Email: reach@harsh1998.dev
+ */
+
+//! Generates zsync 0.6.2 control files so clients holding an older copy of
+//! an artifact only fetch the blocks that changed.
+
+use crate::file_cache::FileCache;
+use chrono::{DateTime, Utc};
+use md4::{Digest, Md4};
+use sha1::Sha1;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Control files kept in memory. One for a multi-gigabyte image runs to
+/// megabytes.
+const MAX_CACHED: usize = 64;
+
+/// Control files keyed by artifact path, regenerated whenever the
+/// artifact's size or modification time changes.
+pub struct Cache {
+    control_files: FileCache<PathBuf, Vec<u8>>,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Cache {
+            control_files: FileCache::new(MAX_CACHED),
+        }
+    }
+}
+
+impl Cache {
+    pub fn control_file(&self, path: &Path) -> io::Result<Arc<Vec<u8>>> {
+        self.control_files
+            .get_or_generate(path.to_path_buf(), path, |metadata| {
+                generate(path, metadata.len(), metadata.modified()?)
+            })
+    }
+}
+
+fn generate(path: &Path, length: u64, modified: SystemTime) -> io::Result<Vec<u8>> {
+    // Same block size choice as zsyncmake.
+    let block_size: usize = if length < 100 * 1024 * 1024 {
+        2048
+    } else {
+        4096
+    };
+    let (seq_matches, rsum_length, checksum_length) = hash_lengths(length, block_size);
+
+    let mut file = File::open(path)?;
+    let mut sha1 = Sha1::new();
+    let mut block_sums = Vec::new();
+    let mut block = vec![0; block_size];
+    loop {
+        let mut filled = 0;
+        while filled < block_size {
+            let bytes_read = file.read(&mut block[filled..])?;
+            if bytes_read == 0 {
+                break;
+            }
+            filled += bytes_read;
+        }
+        if filled == 0 {
+            break;
+        }
+        sha1.update(&block[..filled]);
+
+        // The trailing block is checksummed zero-padded to the full block size.
+        block[filled..].fill(0);
+        block_sums.extend_from_slice(&rolling_checksum(&block)[4 - rsum_length..]);
+        block_sums.extend_from_slice(&Md4::digest(&block)[..checksum_length]);
+        if filled < block_size {
+            break;
+        }
+    }
+
+    let filename = path.file_name().unwrap_or_default().to_string_lossy();
+    let modified: DateTime<Utc> = modified.into();
+    let sha1_hex = crate::to_hex(&sha1.finalize());
+    let mut control = format!(
+        "zsync: 0.6.2\n\
+         Filename: {filename}\n\
+         MTime: {}\n\
+         Blocksize: {block_size}\n\
+         Length: {length}\n\
+         Hash-Lengths: {seq_matches},{rsum_length},{checksum_length}\n\
+         URL: {filename}\n\
+         SHA-1: {sha1_hex}\n\n",
+        modified.to_rfc2822()
+    )
+    .into_bytes();
+    control.extend_from_slice(&block_sums);
+    Ok(control)
+}
+
+/// Picks how many bytes of each weak and strong checksum to store, using
+/// the formulas from zsyncmake so the file is as compact as it would be
+/// had it been generated offline.
+fn hash_lengths(length: u64, block_size: usize) -> (usize, usize, usize) {
+    let blocks = (1 + length / block_size as u64) as f64;
+    let length_bits = (length.max(1) as f64).log2();
+    let seq_matches = if length > block_size as u64 { 2 } else { 1 };
+
+    let rsum_length = ((length_bits + (block_size as f64).log2() - 8.6) / seq_matches as f64 / 8.0)
+        .ceil()
+        .clamp(2.0, 4.0) as usize;
+    let checksum_length = ((20.0 + length_bits + blocks.log2()) / seq_matches as f64 / 8.0).ceil();
+    let minimum_checksum_length = ((7.9 + 20.0 + blocks.log2()) / 8.0).floor();
+    let checksum_length = checksum_length.max(minimum_checksum_length).min(16.0) as usize;
+
+    (seq_matches, rsum_length, checksum_length)
+}
+
+/// The rsync-style weak checksum, stored as big-endian `a` then `b`.
+fn rolling_checksum(block: &[u8]) -> [u8; 4] {
+    let mut a: u16 = 0;
+    let mut b: u16 = 0;
+    for (index, &byte) in block.iter().enumerate() {
+        let weight = (block.len() - index) as u16;
+        a = a.wrapping_add(byte as u16);
+        b = b.wrapping_add(weight.wrapping_mul(byte as u16));
+    }
+    let [a_high, a_low] = a.to_be_bytes();
+    let [b_high, b_low] = b.to_be_bytes();
+    [a_high, a_low, b_high, b_low]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn weak_checksum_matches_rsync() {
+        // a = 1 + 2 + 3, b = 3 * 1 + 2 * 2 + 1 * 3
+        assert_eq!(rolling_checksum(&[1, 2, 3]), [0, 6, 0, 10]);
+        // The sums wrap at 16 bits.
+        let a = (300 * 0xff % 0x1_0000) as u16;
+        assert_eq!(rolling_checksum(&[0xff; 300])[..2], a.to_be_bytes());
+    }
+
+    #[test]
+    fn hash_lengths_match_zsyncmake() {
+        assert_eq!(hash_lengths(5000, 2048), (2, 2, 3));
+        assert_eq!(hash_lengths(100, 2048), (1, 2, 4));
+        // An empty file has no log2 to take; it gets the minimum lengths.
+        assert_eq!(hash_lengths(0, 2048), (1, 2, 3));
+    }
+
+    #[test]
+    fn control_file_describes_every_block() {
+        let scratch = tempfile::tempdir().unwrap();
+        let path = scratch.path().join("image.iso");
+        let contents: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(&path, &contents).unwrap();
+        let modified = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        let control = generate(&path, contents.len() as u64, modified).unwrap();
+        let split = control.windows(2).position(|pair| pair == b"\n\n").unwrap();
+        let header = std::str::from_utf8(&control[..split]).unwrap();
+        let sha1 = crate::to_hex(&Sha1::digest(&contents));
+        assert_eq!(
+            header,
+            format!(
+                "zsync: 0.6.2\nFilename: image.iso\n\
+                 MTime: Tue, 14 Nov 2023 22:13:20 +0000\nBlocksize: 2048\n\
+                 Length: 5000\nHash-Lengths: 2,2,3\nURL: image.iso\nSHA-1: {}",
+                sha1
+            )
+        );
+
+        // Three blocks, each with two bytes of weak and three of strong
+        // checksum; the last is padded with zeros.
+        let sums = &control[split + 2..];
+        assert_eq!(sums.len(), 3 * 5);
+        let mut last = contents[4096..].to_vec();
+        last.resize(2048, 0);
+        assert_eq!(sums[10..12], rolling_checksum(&last)[2..]);
+        assert_eq!(sums[12..], Md4::digest(&last)[..3]);
+    }
+
+    #[test]
+    fn changed_files_are_regenerated() {
+        let scratch = tempfile::tempdir().unwrap();
+        let path = scratch.path().join("a.bin");
+        fs::write(&path, b"first").unwrap();
+        let cache = Cache::default();
+        let first = cache.control_file(&path).unwrap();
+        assert!(Arc::ptr_eq(&first, &cache.control_file(&path).unwrap()));
+
+        fs::write(&path, b"second version").unwrap();
+        let second = cache.control_file(&path).unwrap();
+        assert_ne!(first, second);
+    }
+}